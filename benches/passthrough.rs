@@ -0,0 +1,109 @@
+// Throughput of forwarding a message untouched versus parsing it into a
+// `serde_json::Value` and serializing it again, which is what every message cost
+// before the passthrough fast path.
+//
+// Run with `cargo bench`. The crate has no library target, so the codec is
+// compiled in directly.
+#[allow(dead_code, unused_imports)]
+#[path = "../src/logger.rs"]
+mod logger;
+#[allow(dead_code)]
+#[path = "../src/lsp.rs"]
+mod lsp;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use serde_json::{json, Value};
+
+use lsp::{write_message, Message};
+use tokio::io::AsyncWriteExt;
+
+const MEASURE_FOR: Duration = Duration::from_secs(1);
+
+fn semantic_tokens() -> Value {
+    let data: Vec<u32> = (0..250_000).map(|i| i % 97).collect();
+    json!({ "jsonrpc": "2.0", "id": 42, "result": { "resultId": "7", "data": data } })
+}
+
+fn completion_list() -> Value {
+    let items: Vec<Value> = (0..3_000)
+        .map(|i| {
+            json!({
+                "label": format!("Member{i}"),
+                "kind": 2,
+                "sortText": format!("{i:06}"),
+                "filterText": format!("Member{i}"),
+                "data": { "ResultId": 12, "ProjectId": "1b6e4f0c-0d7e-4c3a-9f1e-2b5c7d8e9f00" }
+            })
+        })
+        .collect();
+    json!({ "jsonrpc": "2.0", "id": 43, "result": { "isIncomplete": false, "items": items } })
+}
+
+fn hover() -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": 44,
+        "result": { "contents": { "kind": "markdown", "value": "```csharp\nint Foo.Bar()\n```" } }
+    })
+}
+
+/// Run `f` repeatedly for about `MEASURE_FOR` and return the mean time per call
+fn measure(mut f: impl FnMut()) -> Duration {
+    f(); // warm up
+    let started = Instant::now();
+    let mut runs = 0u32;
+    while started.elapsed() < MEASURE_FOR {
+        f();
+        runs += 1;
+    }
+    started.elapsed() / runs
+}
+
+fn main() {
+    logger::configure(Some("off"), None, None);
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("runtime");
+
+    println!(
+        "{:<18} {:>10} {:>16} {:>16} {:>8}",
+        "payload", "bytes", "reserialize MB/s", "passthrough MB/s", "speedup"
+    );
+    for (name, value) in [
+        ("semanticTokens", semantic_tokens()),
+        ("completion", completion_list()),
+        ("hover", hover()),
+    ] {
+        let body = Bytes::from(value.to_string());
+        let mut out = Vec::with_capacity(body.len() + 64);
+
+        let reserialize = measure(|| {
+            out.clear();
+            let value: Value = serde_json::from_slice(&body).unwrap();
+            let json = black_box(&value).to_string();
+            rt.block_on(async {
+                let header = format!("Content-Length: {}\r\n\r\n", json.len());
+                out.write_all(header.as_bytes()).await.unwrap();
+                out.write_all(json.as_bytes()).await.unwrap();
+            });
+        });
+        let passthrough = measure(|| {
+            out.clear();
+            let msg = Message::parse(body.clone()).unwrap();
+            rt.block_on(write_message(&mut out, black_box(&msg))).unwrap();
+        });
+
+        let mb_per_s = |d: Duration| body.len() as f64 / d.as_secs_f64() / 1e6;
+        println!(
+            "{:<18} {:>10} {:>16.1} {:>16.1} {:>7.1}x",
+            name,
+            body.len(),
+            mb_per_s(reserialize),
+            mb_per_s(passthrough),
+            reserialize.as_secs_f64() / passthrough.as_secs_f64()
+        );
+    }
+}
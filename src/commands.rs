@@ -0,0 +1,314 @@
+// Roslyn's client-side commands (`roslyn.client.*`). Roslyn puts them in code
+// actions, code lenses and completion items for its VS Code extension to run. The
+// wrapper advertises them as its own in `executeCommandProvider`, so editors send
+// them back through `workspace/executeCommand`, and runs them with standard requests.
+use serde_json::{json, Value};
+
+use crate::lsp::LSP_MESSAGE_TYPE_INFO;
+
+pub const FIX_ALL: &str = "roslyn.client.fixAllCodeAction";
+pub const PEEK_REFERENCES: &str = "roslyn.client.peekReferences";
+pub const COMPLEX_EDIT: &str = "roslyn.client.completionComplexEdit";
+const COMMANDS: &[&str] = &[FIX_ALL, PEEK_REFERENCES, COMPLEX_EDIT];
+
+/// A `roslyn.client.*` command from `workspace/executeCommand`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Apply a code fix everywhere in a document, project or solution
+    FixAll(FixAll),
+    /// Show the references a code lens counted
+    PeekReferences { uri: String, position: Value },
+    /// Apply a completion edit that a plain completion item cannot express
+    ComplexEdit { uri: String, edit: Value, snippet: bool },
+}
+
+/// Arguments of `roslyn.client.fixAllCodeAction`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixAll {
+    pub title: String,
+    /// Scopes Roslyn offers, e.g. "Document", "Project", "Solution"
+    pub flavors: Vec<String>,
+    /// The code action's resolve data, sent back to Roslyn unchanged
+    pub data: Value,
+}
+
+impl Command {
+    /// Parse `workspace/executeCommand` params; `None` for commands Roslyn runs itself
+    pub fn parse(params: &Value) -> Option<Result<Self, String>> {
+        let name = params.get("command")?.as_str()?;
+        if !COMMANDS.contains(&name) {
+            return None;
+        }
+        let arguments = params.get("arguments").and_then(Value::as_array);
+        let parsed = Self::from_arguments(name, arguments.map_or(&[], Vec::as_slice));
+        Some(parsed.ok_or_else(|| format!("Unexpected arguments for {name}")))
+    }
+
+    fn from_arguments(name: &str, arguments: &[Value]) -> Option<Self> {
+        match name {
+            FIX_ALL => {
+                let data = arguments.first()?.clone();
+                let title = data.get("UniqueIdentifier").and_then(Value::as_str).unwrap_or("Fix all");
+                let flavors = data.get("FixAllFlavors").and_then(Value::as_array)?;
+                Some(Command::FixAll(FixAll {
+                    title: title.to_string(),
+                    flavors: flavors.iter().filter_map(Value::as_str).map(String::from).collect(),
+                    data,
+                }))
+            }
+            PEEK_REFERENCES => Some(Command::PeekReferences {
+                uri: document_uri(arguments.first()?)?,
+                position: arguments.get(1)?.clone(),
+            }),
+            COMPLEX_EDIT => Some(Command::ComplexEdit {
+                uri: document_uri(arguments.first()?)?,
+                edit: arguments.get(1)?.clone(),
+                snippet: arguments.get(2).and_then(Value::as_bool).unwrap_or(false),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::FixAll(_) => FIX_ALL,
+            Command::PeekReferences { .. } => PEEK_REFERENCES,
+            Command::ComplexEdit { .. } => COMPLEX_EDIT,
+        }
+    }
+}
+
+/// A document as a URI string or a `TextDocumentIdentifier`
+fn document_uri(argument: &Value) -> Option<String> {
+    let uri = argument.get("uri").unwrap_or(argument);
+    uri.as_str().map(String::from)
+}
+
+/// Add the commands the wrapper runs to Roslyn's `executeCommandProvider`
+pub fn advertise(capabilities: &mut Value) {
+    let Some(capabilities) = capabilities.as_object_mut() else {
+        return;
+    };
+    let provider = capabilities.entry("executeCommandProvider").or_insert_with(|| json!({}));
+    if !provider.is_object() {
+        *provider = json!({});
+    }
+    let commands = &mut provider["commands"];
+    if !commands.is_array() {
+        *commands = json!([]);
+    }
+    if let Some(commands) = commands.as_array_mut() {
+        for command in COMMANDS {
+            if !commands.iter().any(|c| c == command) {
+                commands.push(json!(command));
+            }
+        }
+    }
+}
+
+/// `window/showMessageRequest` params asking where to apply a fix
+pub fn scope_prompt(fix_all: &FixAll) -> Value {
+    let actions: Vec<Value> = fix_all.flavors.iter().map(|scope| json!({ "title": scope })).collect();
+    json!({
+        "type": LSP_MESSAGE_TYPE_INFO,
+        "message": format!("{}: apply to", fix_all.title),
+        "actions": actions
+    })
+}
+
+/// `codeAction/resolveFixAll` params for the chosen scope
+pub fn resolve_fix_all(fix_all: &FixAll, scope: &str) -> Value {
+    json!({ "title": fix_all.title, "data": fix_all.data, "scope": scope })
+}
+
+/// `textDocument/references` params for a references code lens
+pub fn references(uri: &str, position: &Value) -> Value {
+    json!({
+        "textDocument": { "uri": uri },
+        "position": position,
+        "context": { "includeDeclaration": false }
+    })
+}
+
+/// `workspace/applyEdit` params
+pub fn apply_edit(label: &str, edit: Value) -> Value {
+    json!({ "label": label, "edit": edit })
+}
+
+/// Workspace edit for a complex completion edit; snippets are inserted as plain text
+pub fn complex_edit(uri: &str, edit: &Value, snippet: bool) -> Value {
+    let mut edit = edit.clone();
+    if let Some(text) = edit.get("newText").and_then(Value::as_str).filter(|_| snippet) {
+        edit["newText"] = json!(snippet_to_plain(text));
+    }
+    let mut changes = serde_json::Map::new();
+    changes.insert(uri.to_string(), json!([edit]));
+    json!({ "changes": changes })
+}
+
+/// Rewrite a completion item whose edit Roslyn left to `roslyn.client.completionComplexEdit`
+/// into a standard `textEdit` plus `additionalTextEdits`; returns whether it changed.
+///
+/// The main edit must stay on one line, so a multi-line edit replaces the part on
+/// its last line and deletes the lines before it with an additional edit. The caret
+/// position Roslyn suggests is not kept; snippets keep their own tab stops.
+pub fn inline_complex_edit(item: &mut Value) -> bool {
+    let Some(Ok(Command::ComplexEdit { edit, snippet, .. })) = item.get("command").and_then(Command::parse) else {
+        return false;
+    };
+    let (Some(start), Some(end)) = (edit.pointer("/range/start"), edit.pointer("/range/end")) else {
+        return false;
+    };
+    let (Some(start_line), Some(end_line)) = (start.get("line"), end.get("line")) else {
+        return false;
+    };
+    let Some(item) = item.as_object_mut() else {
+        return false;
+    };
+
+    let main_start = if start_line == end_line {
+        start.clone()
+    } else {
+        json!({ "line": end_line, "character": 0 })
+    };
+    if start_line != end_line {
+        let delete = json!({ "range": { "start": start, "end": main_start }, "newText": "" });
+        let additional = item.entry("additionalTextEdits").or_insert_with(|| json!([]));
+        match additional.as_array_mut() {
+            Some(edits) => edits.push(delete),
+            None => *additional = json!([delete]),
+        }
+    }
+    item.insert(
+        "textEdit".to_string(),
+        json!({ "range": { "start": main_start, "end": end }, "newText": edit["newText"] }),
+    );
+    item.insert("insertTextFormat".to_string(), json!(if snippet { 2 } else { 1 }));
+    for replaced in ["command", "insertText", "textEditText"] {
+        item.remove(replaced);
+    }
+    true
+}
+
+/// Apply [`inline_complex_edit`] to a completion list or a resolved item; returns how many changed
+pub fn inline_complex_edits(result: &mut Value) -> usize {
+    if result.get("label").is_some() {
+        return usize::from(inline_complex_edit(result));
+    }
+    let items = if result.get("items").is_some() { &mut result["items"] } else { result };
+    items.as_array_mut().map_or(0, |items| {
+        items.iter_mut().map(inline_complex_edit).filter(|changed| *changed).count()
+    })
+}
+
+/// `window/showDocument` params that open a location and select it
+pub fn show_document(location: &Value) -> Option<Value> {
+    Some(json!({
+        "uri": location.get("uri")?,
+        "takeFocus": true,
+        "selection": location.get("range")?
+    }))
+}
+
+/// Drop snippet tab stops and keep placeholder text: `Foo(${1:x})$0` → `Foo(x)`
+pub fn snippet_to_plain(snippet: &str) -> String {
+    let mut plain = String::with_capacity(snippet.len());
+    let mut chars = snippet.chars().peekable();
+    // Placeholders being read, so their closing braces can be dropped
+    let mut open_placeholders = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    plain.push(escaped);
+                }
+            }
+            '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                while chars.peek().is_some_and(char::is_ascii_digit) {
+                    chars.next();
+                }
+            }
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                while chars.peek().is_some_and(char::is_ascii_digit) {
+                    chars.next();
+                }
+                if chars.peek() == Some(&':') {
+                    chars.next();
+                }
+                open_placeholders += 1;
+            }
+            '}' if open_placeholders > 0 => open_placeholders -= 1,
+            _ => plain.push(c),
+        }
+    }
+    plain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_roslyn_client_commands_are_parsed() {
+        assert_eq!(Command::parse(&json!({ "command": "dotnet.test.run", "arguments": [] })), None);
+
+        let data = json!({ "UniqueIdentifier": "Remove unused usings", "FixAllFlavors": ["Document", "Solution"] });
+        let fix_all = Command::parse(&json!({ "command": FIX_ALL, "arguments": [data] }));
+        let Some(Ok(Command::FixAll(fix_all))) = fix_all else {
+            panic!("fix all parses");
+        };
+        assert_eq!(fix_all.flavors, ["Document", "Solution"]);
+        assert_eq!(resolve_fix_all(&fix_all, "Solution")["title"], "Remove unused usings");
+
+        let peek = json!({ "command": PEEK_REFERENCES, "arguments": ["file:///a.cs", { "line": 1, "character": 2 }] });
+        assert!(matches!(Command::parse(&peek), Some(Ok(Command::PeekReferences { .. }))));
+        assert!(Command::parse(&json!({ "command": COMPLEX_EDIT })).unwrap().is_err());
+    }
+
+    #[test]
+    fn commands_are_added_to_the_execute_command_provider() {
+        let mut capabilities = json!({ "executeCommandProvider": { "commands": [FIX_ALL, "other"] } });
+        advertise(&mut capabilities);
+        let commands = capabilities["executeCommandProvider"]["commands"].as_array().unwrap();
+        assert_eq!(commands.len(), 4);
+
+        let mut empty = json!({});
+        advertise(&mut empty);
+        assert_eq!(empty["executeCommandProvider"]["commands"], json!(COMMANDS));
+    }
+
+    #[test]
+    fn complex_completion_edits_become_standard_edits() {
+        let edit = json!({
+            "range": { "start": { "line": 2, "character": 4 }, "end": { "line": 3, "character": 17 } },
+            "newText": "public override string ToString()\n{\n}"
+        });
+        let mut list = json!({ "items": [
+            { "label": "Equals(object? obj)" },
+            {
+                "label": "ToString()",
+                "textEditText": "ToString",
+                "command": { "title": "", "command": COMPLEX_EDIT, "arguments": [{ "uri": "file:///a.cs" }, edit, false, 40] }
+            }
+        ] });
+        assert_eq!(inline_complex_edits(&mut list), 1);
+        let item = &list["items"][1];
+        assert!(item.get("command").is_none() && item.get("textEditText").is_none());
+        assert_eq!(item["textEdit"]["range"]["start"], json!({ "line": 3, "character": 0 }));
+        assert_eq!(item["textEdit"]["newText"], edit["newText"]);
+        assert_eq!(
+            item["additionalTextEdits"],
+            json!([{ "range": { "start": { "line": 2, "character": 4 }, "end": { "line": 3, "character": 0 } }, "newText": "" }])
+        );
+        assert_eq!(list["items"][0], json!({ "label": "Equals(object? obj)" }));
+    }
+
+    #[test]
+    fn snippets_become_plain_text() {
+        assert_eq!(snippet_to_plain("Foo(${1:x}, ${2:y})$0"), "Foo(x, y)");
+        assert_eq!(snippet_to_plain("a \\$1 {b} ${3}"), "a $1 {b} ");
+        let edit = json!({ "range": {}, "newText": "Bar($0)" });
+        assert_eq!(complex_edit("file:///a.cs", &edit, true)["changes"]["file:///a.cs"][0]["newText"], "Bar()");
+    }
+}
@@ -0,0 +1,334 @@
+// Wrapper configuration, merged from these layers (later ones win):
+//   1. built-in defaults
+//   2. the user config file, `config.json` in the wrapper's config directory
+//   3. the workspace config file, `.roslyn-wrapper.json` in the workspace root
+//   4. `ROSLYN_WRAPPER_*` environment variables, e.g. `ROSLYN_WRAPPER_LOG_LEVEL=debug`
+//   5. `--set key=value` command-line options
+//   6. `initializationOptions` from the editor's `initialize` request
+//
+// Every layer uses the `initializationOptions` names. Object settings (`logLevels`,
+// `requestTimeouts`) are merged key by key; anything else is replaced. Unknown keys
+// and values of the wrong type are reported and skipped, not fatal.
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::logger;
+
+/// Name of the per-workspace config file, looked up in the workspace root
+pub const WORKSPACE_FILE: &str = ".roslyn-wrapper.json";
+
+const ENV_PREFIX: &str = "ROSLYN_WRAPPER_";
+
+/// Where a setting came from, lowest precedence first
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Origin {
+    Default,
+    UserFile,
+    WorkspaceFile,
+    Environment,
+    CommandLine,
+    InitializationOptions,
+}
+
+impl Origin {
+    fn name(self) -> &'static str {
+        match self {
+            Origin::Default => "default",
+            Origin::UserFile => "user config",
+            Origin::WorkspaceFile => "workspace config",
+            Origin::Environment => "environment",
+            Origin::CommandLine => "command line",
+            Origin::InitializationOptions => "initializationOptions",
+        }
+    }
+}
+
+/// The effective configuration; see the README for what each setting does
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct WrapperConfig {
+    pub log_level: String,
+    pub log_levels: Option<Value>,
+    pub log_format: String,
+    pub log_file: Option<String>,
+    pub log_directory: Option<String>,
+    // Unset until the editor's `initialize`, so startup entries stay buffered for it
+    pub client_log_level: Option<String>,
+    pub log_max_bytes: u64,
+    pub log_max_age_hours: u64,
+    pub log_max_files: u64,
+    pub log_compress: bool,
+    pub trace_max_bytes: u64,
+    pub trace_redact_documents: bool,
+    pub request_timeouts: Option<Value>,
+    pub auto_restore: bool,
+    pub hold_requests_until_loaded: Value,
+    pub solution: Option<String>,
+    // Layer each setting was taken from, for `log_effective`
+    #[serde(skip)]
+    origins: BTreeMap<String, Origin>,
+}
+
+impl Default for WrapperConfig {
+    fn default() -> Self {
+        Self {
+            log_level: "info".to_string(),
+            log_levels: None,
+            log_format: "text".to_string(),
+            log_file: None,
+            log_directory: None,
+            client_log_level: None,
+            log_max_bytes: 10 * 1024 * 1024,
+            log_max_age_hours: 0,
+            log_max_files: 5,
+            log_compress: false,
+            trace_max_bytes: logger::DEFAULT_TRACE_MAX_BYTES as u64,
+            trace_redact_documents: true,
+            request_timeouts: None,
+            auto_restore: true,
+            hold_requests_until_loaded: Value::Bool(false),
+            solution: None,
+            origins: BTreeMap::new(),
+        }
+    }
+}
+
+impl WrapperConfig {
+    /// Apply the logging settings to the logger, except `clientLogLevel`: the editor
+    /// decides that in `initialize` (see `logger::mirror_to_client`)
+    pub fn apply_logging(&self) {
+        logger::configure(Some(&self.log_level), None, None);
+        logger::configure_sources(self.log_levels.as_ref().unwrap_or(&json!({})));
+        logger::set_format(&self.log_format);
+        logger::configure_trace(Some(self.trace_max_bytes), Some(self.trace_redact_documents));
+        logger::configure_rotation(
+            Some(self.log_max_bytes),
+            Some(self.log_max_age_hours),
+            Some(self.log_max_files),
+            Some(self.log_compress),
+        );
+        if self.log_file.is_some() || self.log_directory.is_some() {
+            logger::configure(None, self.log_file.as_deref(), self.log_directory.as_deref());
+        }
+    }
+
+    /// Whether `other` would set up a session differently; the solution is compared on its own
+    pub fn session_differs(&self, other: &WrapperConfig) -> bool {
+        let other = WrapperConfig {
+            solution: self.solution.clone(),
+            origins: self.origins.clone(),
+            ..other.clone()
+        };
+        *self != other
+    }
+
+    /// Log every setting with the layer it came from
+    pub fn log_effective(&self) {
+        let Ok(Value::Object(settings)) = serde_json::to_value(self) else {
+            return;
+        };
+        let settings: Vec<String> = settings
+            .iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| {
+                let origin = self.origins.get(key).copied().unwrap_or(Origin::Default);
+                format!("{key}={value} ({})", origin.name())
+            })
+            .collect();
+        logger::info(format!("[roslyn_wrapper] Effective configuration: {}", settings.join(", ")));
+    }
+}
+
+/// Read every layer and merge them. `workspace` is where the workspace config file
+/// is looked up; `command_line` holds the `--set` options.
+pub fn load(
+    workspace: Option<&Path>,
+    command_line: &Map<String, Value>,
+    initialization_options: Option<&Value>,
+) -> WrapperConfig {
+    let mut layers = Vec::new();
+    if let Some(path) = user_file() {
+        layers.extend(read_file(&path).map(|layer| (Origin::UserFile, layer)));
+    }
+    if let Some(workspace) = workspace {
+        layers.extend(read_file(&workspace.join(WORKSPACE_FILE)).map(|layer| (Origin::WorkspaceFile, layer)));
+    }
+    layers.push((Origin::Environment, environment(std::env::vars())));
+    layers.push((Origin::CommandLine, command_line.clone()));
+    match initialization_options {
+        Some(Value::Object(options)) => layers.push((Origin::InitializationOptions, options.clone())),
+        Some(Value::Null) | None => {}
+        Some(_) => logger::warn("[roslyn_wrapper] Ignoring initializationOptions: expected an object"),
+    }
+
+    let (config, ignored) = resolve(layers);
+    for warning in ignored {
+        logger::warn(format!("[roslyn_wrapper] {warning}"));
+    }
+    config
+}
+
+/// `--set key=value` options as a layer; values are JSON, or taken as a string
+pub fn command_line(settings: &[String]) -> Result<Map<String, Value>, String> {
+    let mut layer = Map::new();
+    for setting in settings {
+        let Some((key, value)) = setting.split_once('=') else {
+            return Err(format!("Invalid setting: {setting} (expected key=value)"));
+        };
+        layer.insert(key.trim().to_string(), parse_value(value));
+    }
+    Ok(layer)
+}
+
+/// `config.json` in the wrapper's config directory, e.g. `~/.config/roslyn-wrapper` on Linux
+fn user_file() -> Option<PathBuf> {
+    ProjectDirs::from("com", "github", "roslyn-wrapper").map(|dirs| dirs.config_dir().join("config.json"))
+}
+
+/// A config file as a layer; a missing file is an empty layer
+fn read_file(path: &Path) -> Option<Map<String, Value>> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(Value::Object(layer)) => Some(layer),
+        Ok(_) => {
+            logger::warn(format!("[roslyn_wrapper] Ignoring {}: expected a JSON object", path.display()));
+            None
+        }
+        Err(e) => {
+            logger::warn(format!("[roslyn_wrapper] Ignoring {}: {e}", path.display()));
+            None
+        }
+    }
+}
+
+/// `ROSLYN_WRAPPER_*` variables as a layer: `ROSLYN_WRAPPER_LOG_MAX_FILES` sets `logMaxFiles`
+fn environment(vars: impl IntoIterator<Item = (String, String)>) -> Map<String, Value> {
+    let mut layer = Map::new();
+    for (name, value) in vars {
+        let Some(name) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let mut key = String::new();
+        for (i, word) in name.split('_').filter(|word| !word.is_empty()).enumerate() {
+            let word = word.to_lowercase();
+            match i {
+                0 => key.push_str(&word),
+                _ => {
+                    let mut chars = word.chars();
+                    key.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+                    key.push_str(chars.as_str());
+                }
+            }
+        }
+        layer.insert(key, parse_value(&value));
+    }
+    layer
+}
+
+/// Numbers, booleans and objects are read as JSON; anything else is a string
+fn parse_value(value: &str) -> Value {
+    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))
+}
+
+/// Merge layers over the defaults, lowest precedence first. Also returns why keys
+/// were skipped.
+fn resolve(layers: impl IntoIterator<Item = (Origin, Map<String, Value>)>) -> (WrapperConfig, Vec<String>) {
+    let Ok(Value::Object(defaults)) = serde_json::to_value(WrapperConfig::default()) else {
+        unreachable!("the config serializes to an object");
+    };
+    let mut merged = Map::new();
+    let mut origins = BTreeMap::new();
+    let mut ignored = Vec::new();
+    for (origin, layer) in layers {
+        for (key, value) in layer {
+            if !defaults.contains_key(&key) {
+                ignored.push(format!("Ignoring unknown setting {key} from {}", origin.name()));
+                continue;
+            }
+            if value.is_null() {
+                continue;
+            }
+            // Check the type on its own so one bad value does not discard the layer
+            if let Err(e) = serde_json::from_value::<WrapperConfig>(json!({ key.as_str(): value })) {
+                ignored.push(format!("Ignoring {key} from {}: {e}", origin.name()));
+                continue;
+            }
+            match (merged.get_mut(&key), value) {
+                (Some(Value::Object(current)), Value::Object(value)) => current.extend(value),
+                (_, value) => {
+                    merged.insert(key.clone(), value);
+                }
+            }
+            origins.insert(key, origin);
+        }
+    }
+    let mut config: WrapperConfig = serde_json::from_value(Value::Object(merged)).unwrap_or_default();
+    config.origins = origins;
+    (config, ignored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap()
+    }
+
+    #[test]
+    fn later_layers_win_and_objects_merge_by_key() {
+        let (config, ignored) = resolve([
+            (Origin::UserFile, layer(json!({ "logLevel": "debug", "requestTimeouts": { "default": 10, "textDocument/hover": 2 } }))),
+            (Origin::WorkspaceFile, layer(json!({ "solution": "file:///work/App.sln", "requestTimeouts": { "textDocument/hover": 5 } }))),
+            (Origin::Environment, layer(json!({ "logLevel": "trace" }))),
+            (Origin::InitializationOptions, layer(json!({ "logLevel": "warn", "logFile": null }))),
+        ]);
+        assert!(ignored.is_empty());
+        assert_eq!(config.log_level, "warn");
+        assert_eq!(config.solution.as_deref(), Some("file:///work/App.sln"));
+        assert_eq!(config.request_timeouts, Some(json!({ "default": 10, "textDocument/hover": 5 })));
+        assert_eq!(config.log_max_files, 5);
+        assert_eq!(config.origins["logLevel"], Origin::InitializationOptions);
+        assert_eq!(config.origins["requestTimeouts"], Origin::WorkspaceFile);
+        assert!(!config.origins.contains_key("logFile"));
+    }
+
+    #[test]
+    fn unknown_keys_and_wrong_types_are_skipped() {
+        let (config, ignored) = resolve([(
+            Origin::UserFile,
+            layer(json!({ "logLevl": "debug", "logMaxFiles": "many", "logCompress": true })),
+        )]);
+        assert!(config.log_compress);
+        assert_eq!(config.log_max_files, 5);
+        assert_eq!(ignored.len(), 2);
+        assert!(ignored.contains(&"Ignoring unknown setting logLevl from user config".to_string()));
+        assert!(ignored.iter().any(|warning| warning.starts_with("Ignoring logMaxFiles from user config: invalid type")));
+    }
+
+    #[test]
+    fn environment_and_command_line_values_are_typed() {
+        let vars = [
+            ("ROSLYN_WRAPPER_LOG_LEVEL", "debug"),
+            ("ROSLYN_WRAPPER_LOG_MAX_AGE_HOURS", "12"),
+            ("ROSLYN_WRAPPER_LOG_COMPRESS", "true"),
+            ("ROSLYN_WRAPPER_LOG_LEVELS", r#"{"protocol":"trace"}"#),
+            ("PATH", "/usr/bin"),
+        ];
+        let layer = environment(vars.map(|(name, value)| (name.to_string(), value.to_string())));
+        assert_eq!(
+            Value::Object(layer),
+            json!({ "logLevel": "debug", "logMaxAgeHours": 12, "logCompress": true, "logLevels": { "protocol": "trace" } })
+        );
+
+        let settings = ["solution=file:///work/App.sln".to_string(), "logMaxFiles=3".to_string()];
+        assert_eq!(
+            Value::Object(command_line(&settings).unwrap()),
+            json!({ "solution": "file:///work/App.sln", "logMaxFiles": 3 })
+        );
+        assert!(command_line(&["logLevel".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+// Roslyn's custom server-to-client methods. Generic editors answer these with
+// MethodNotFound or never at all, which can leave Roslyn waiting, so every method
+// the wrapper knows about is handled here instead of being forwarded as is.
+use serde_json::{json, Value};
+
+use crate::logger;
+use crate::lsp::{LSP_MESSAGE_TYPE_ERROR, LSP_MESSAGE_TYPE_INFO, LSP_MESSAGE_TYPE_WARNING};
+
+// Roslyn Message Type Constants (for window/_roslyn_showToast)
+const ROSLYN_MESSAGE_TYPE_ERROR: i64 = 3;
+const ROSLYN_MESSAGE_TYPE_WARNING: i64 = 1;
+const ROSLYN_MESSAGE_TYPE_INFO: i64 = 2;
+
+/// What the wrapper does with a custom Roslyn method
+pub enum Handler {
+    /// Handled by the wrapper itself
+    Local(Local),
+    /// Rewritten into a standard LSP message for the editor
+    Translate(fn(Value) -> Value),
+    /// Requests are answered with this result right away; notifications are dropped
+    Default(fn() -> Value),
+    /// Not implemented by the wrapper or generic editors: requests are answered with
+    /// MethodNotFound and logged as warnings; notifications are dropped
+    Unsupported,
+}
+
+/// Custom methods the router implements itself
+pub enum Local {
+    /// Packages are missing: run `dotnet restore`, then answer; never reaches the editor
+    Restore,
+    /// Every project has been loaded; still forwarded for editors that listen for it
+    SolutionLoaded,
+}
+
+// Exact method names, or prefixes ending in `*`; the first match wins
+const HANDLERS: &[(&str, Handler)] = &[
+    ("window/_roslyn_showToast", Handler::Translate(map_toast)),
+    ("workspace/_roslyn_projectNeedsRestore", Handler::Local(Local::Restore)),
+    ("workspace/projectInitializationComplete", Handler::Local(Local::SolutionLoaded)),
+    // With cohosting the Razor source generator produces the C# of Razor documents inside
+    // Roslyn, so there is no separately generated file to point it at; C# completion and
+    // diagnostics in .razor/.cshtml files come from the generator
+    ("razor/provideDynamicFileInfo", Handler::Default(null)),
+    // HTML bridge: the VS Code extension keeps HTML copies of Razor documents and answers
+    // for their HTML parts. Other editors have no HTML side, so the copy is acknowledged
+    // and Roslyn's HTML requests go unanswered, which it treats as no HTML results
+    ("razor/updateHtml", Handler::Default(razor_html)),
+    ("razor/*", Handler::Unsupported),
+    ("textDocument/_vs_*", Handler::Unsupported),
+    ("workspace/_roslyn_*", Handler::Unsupported),
+];
+
+/// Handler for a Roslyn method, if it is one of the custom ones
+pub fn handler(method: &str) -> Option<&'static Handler> {
+    HANDLERS
+        .iter()
+        .find(|(pattern, _)| match pattern.strip_suffix('*') {
+            Some(prefix) => method.starts_with(prefix),
+            None => method == *pattern,
+        })
+        .map(|(_, handler)| handler)
+}
+
+fn null() -> Value {
+    Value::Null
+}
+
+/// Empty answer for the HTML side of a Razor document, saying once per process why
+fn razor_html() -> Value {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    WARNED.call_once(|| {
+        logger::warn(
+            "[roslyn_wrapper] Razor HTML features (HTML completion, formatting and hover) are not available \
+             outside VS Code; C# features in Razor files are unaffected",
+        );
+    });
+    Value::Null
+}
+
+/// Map Roslyn's toast notification to a standard `window/showMessage`
+fn map_toast(msg: Value) -> Value {
+    let Some(params) = msg.get("params") else {
+        return msg;
+    };
+    let message = params.get("message").and_then(|v| v.as_str()).unwrap_or("");
+    let roslyn_type = params
+        .get("messageType")
+        .and_then(|v| v.as_i64())
+        .unwrap_or(ROSLYN_MESSAGE_TYPE_INFO);
+    // Map Roslyn message types to LSP: 3->1 (Error), 1->2 (Warning), 2->3 (Info)
+    let lsp_type = match roslyn_type {
+        ROSLYN_MESSAGE_TYPE_ERROR => LSP_MESSAGE_TYPE_ERROR,
+        ROSLYN_MESSAGE_TYPE_WARNING => LSP_MESSAGE_TYPE_WARNING,
+        ROSLYN_MESSAGE_TYPE_INFO => LSP_MESSAGE_TYPE_INFO,
+        _ => LSP_MESSAGE_TYPE_INFO,
+    };
+
+    logger::debug("[roslyn_wrapper] Rewriting _roslyn_showToast to window/showMessage");
+    json!({
+        "jsonrpc": "2.0",
+        "method": "window/showMessage",
+        "params": {
+            "type": lsp_type,
+            "message": message
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_names_win_over_prefixes() {
+        assert!(matches!(
+            handler("workspace/_roslyn_projectNeedsRestore"),
+            Some(Handler::Local(Local::Restore))
+        ));
+        assert!(matches!(handler("workspace/_roslyn_somethingNew"), Some(Handler::Unsupported)));
+        assert!(matches!(handler("razor/provideDynamicFileInfo"), Some(Handler::Default(_))));
+        assert!(matches!(handler("razor/updateHtml"), Some(Handler::Default(_))));
+        assert!(matches!(handler("razor/somethingNew"), Some(Handler::Unsupported)));
+        assert!(matches!(handler("textDocument/_vs_getProjectContexts"), Some(Handler::Unsupported)));
+        assert!(handler("textDocument/publishDiagnostics").is_none());
+        assert!(handler("workspace/_roslyn").is_none());
+    }
+
+    #[test]
+    fn toast_becomes_show_message() {
+        let toast = json!({
+            "jsonrpc": "2.0",
+            "method": "window/_roslyn_showToast",
+            "params": { "message": "Restore failed", "messageType": ROSLYN_MESSAGE_TYPE_ERROR }
+        });
+        let Some(Handler::Translate(translate)) = handler("window/_roslyn_showToast") else {
+            panic!("toast is translated");
+        };
+        assert_eq!(
+            translate(toast),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "window/showMessage",
+                "params": { "type": LSP_MESSAGE_TYPE_ERROR, "message": "Restore failed" }
+            })
+        );
+    }
+}
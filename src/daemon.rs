@@ -0,0 +1,274 @@
+// Shared Roslyn daemon: one background Roslyn host per solution, reused by every
+// editor session that opens the same solution.
+//
+// The first wrapper started with `--daemon` spawns `roslyn-wrapper --daemon-host <socket>`
+// in the background and then only relays bytes between its stdio and the host socket.
+// Later wrappers for the same solution find the socket and connect straight away.
+// Wrappers that find no host take a lock next to the socket before starting one, so
+// two editors opening the same solution together end up sharing a single host.
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::lsp::{read_lsp_message, write_message, Incoming};
+use crate::{logger, path_utils};
+
+pub const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 600;
+
+// How long a new client waits for a freshly spawned host (includes a first Roslyn download)
+const HOST_STARTUP_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Identify the solution an `initialize` request is about.
+///
+/// Uses the same lookup as `solution/open`: the configured solution first, then
+/// discovery under the workspace roots, then the workspace root itself.
+pub fn solution_key(initialize: &Value) -> String {
+    let params = initialize.get("params");
+    if let Some(solution) = params
+        .and_then(|p| p.get("initializationOptions"))
+        .and_then(|o| o.get("solution"))
+        .and_then(|v| v.as_str())
+    {
+        return solution.to_string();
+    }
+
+    let mut roots = Vec::new();
+    if let Some(root_uri) = params.and_then(|p| p.get("rootUri")).and_then(|v| v.as_str()) {
+        roots.push(root_uri.to_string());
+    }
+    if let Some(folders) = params
+        .and_then(|p| p.get("workspaceFolders"))
+        .and_then(|v| v.as_array())
+    {
+        roots.extend(
+            folders
+                .iter()
+                .filter_map(|f| f.get("uri").and_then(|u| u.as_str()))
+                .map(|s| s.to_string()),
+        );
+    }
+    for root in &roots {
+        if let Some(found) = path_utils::url_to_path(root)
+            .ok()
+            .and_then(|p| path_utils::try_find_solution_or_project(&p))
+        {
+            return found;
+        }
+    }
+
+    roots.into_iter().next().unwrap_or_else(|| "default".to_string())
+}
+
+/// FNV-1a, stable across builds so every wrapper derives the same socket name
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Per-user socket for the daemon serving `key`
+pub fn socket_path(key: &str) -> io::Result<PathBuf> {
+    let base = match dirs::runtime_dir() {
+        Some(dir) => dir.join("roslyn-wrapper"),
+        None => crate::download::get_cache_dir()
+            .map_err(|e| io::Error::other(e.to_string()))?
+            .join("daemon"),
+    };
+    std::fs::create_dir_all(&base)?;
+    #[cfg(unix)]
+    {
+        // Only the owning user may talk to their daemons
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&base, std::fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(base.join(format!("{:016x}.sock", fnv1a(key))))
+}
+
+/// Relay an editor session on stdio to the daemon for its solution, starting it if needed
+#[cfg(unix)]
+pub async fn run_client<R: AsyncBufRead + Unpin>(mut stdin: R, host_args: &[String]) -> io::Result<()> {
+    use tokio::net::UnixStream;
+
+    // The first message decides which daemon to use
+    let initialize = match read_lsp_message(&mut stdin).await? {
+        Some(Incoming::Message(msg)) => msg,
+        Some(Incoming::Malformed(reason)) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Malformed initialize request: {reason}"),
+            ))
+        }
+        None => return Ok(()),
+    };
+    let key = solution_key(&initialize.to_value());
+    let socket = socket_path(&key)?;
+
+    let stream = match UnixStream::connect(&socket).await {
+        Ok(stream) => stream,
+        Err(_) => {
+            let _lock = StartupLock::acquire(&socket).await?;
+            // Another wrapper may have started the host while this one waited for the lock
+            match UnixStream::connect(&socket).await {
+                Ok(stream) => stream,
+                Err(_) => {
+                    logger::info(format!(
+                        "[roslyn_wrapper] Starting Roslyn daemon for {key} at {}",
+                        socket.display()
+                    ));
+                    spawn_host(&socket, host_args).await?
+                }
+            }
+        }
+    };
+    logger::info(format!("[roslyn_wrapper] Using Roslyn daemon for {key} at {}", socket.display()));
+
+    let (mut reader, mut writer) = stream.into_split();
+    write_message(&mut writer, &initialize).await?;
+
+    let to_daemon = async move {
+        let _ = pump(&mut stdin, &mut writer).await;
+        // Dropping the write half tells the daemon this client is done
+    };
+    let mut stdout = tokio::io::stdout();
+    let from_daemon = pump(&mut reader, &mut stdout);
+    tokio::pin!(from_daemon);
+    tokio::select! {
+        result = &mut from_daemon => result?,
+        // The daemon answers the editor's last messages before closing its side
+        _ = to_daemon => from_daemon.await?,
+    }
+    logger::info("[roslyn_wrapper] Roslyn daemon closed the connection");
+    Ok(())
+}
+
+/// Exclusive lock on `<socket>.lock`, held from checking for a host until the one this
+/// wrapper started accepts connections; released when dropped
+#[cfg(unix)]
+struct StartupLock(#[allow(dead_code)] std::fs::File);
+
+#[cfg(unix)]
+impl StartupLock {
+    async fn acquire(socket: &Path) -> io::Result<Self> {
+        use std::os::fd::AsRawFd;
+
+        let path = socket.with_extension("lock");
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)?;
+            // SAFETY: flock on a descriptor owned by `file`, which outlives the call
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(StartupLock(file))
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+}
+
+/// Start a detached daemon host and wait until it accepts connections
+#[cfg(unix)]
+async fn spawn_host(socket: &Path, host_args: &[String]) -> io::Result<tokio::net::UnixStream> {
+    use std::process::Stdio;
+    use tokio::process::Command;
+
+    let mut host = Command::new(std::env::current_exe()?)
+        .arg("--daemon-host")
+        .arg(socket)
+        .args(host_args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // Own process group, so the editor killing this wrapper leaves the daemon alone
+        .process_group(0)
+        .spawn()?;
+
+    let started = Instant::now();
+    loop {
+        if let Ok(stream) = tokio::net::UnixStream::connect(socket).await {
+            return Ok(stream);
+        }
+        if let Some(status) = host.try_wait()? {
+            return Err(io::Error::other(format!(
+                "Roslyn daemon exited during startup ({status})"
+            )));
+        }
+        if started.elapsed() > HOST_STARTUP_TIMEOUT {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out waiting for the Roslyn daemon to start",
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+/// Whether a live daemon already owns `socket`
+#[cfg(unix)]
+pub fn is_running(socket: &Path) -> bool {
+    std::os::unix::net::UnixStream::connect(socket).is_ok()
+}
+
+/// Copy bytes as they arrive, flushing each chunk so LSP frames are not held back
+#[cfg(unix)]
+async fn pump<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(reader: &mut R, writer: &mut W) -> io::Result<()> {
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        writer.write_all(&buf[..n]).await?;
+        writer.flush().await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn solution_key_prefers_configured_solution() {
+        let init = json!({
+            "params": {
+                "rootUri": "file:///nonexistent/work",
+                "initializationOptions": { "solution": "file:///work/App.sln" }
+            }
+        });
+        assert_eq!(solution_key(&init), "file:///work/App.sln");
+
+        let init = json!({ "params": { "rootUri": "file:///nonexistent/work" } });
+        assert_eq!(solution_key(&init), "file:///nonexistent/work");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn startup_lock_lets_one_wrapper_start_the_host_at_a_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("daemon.sock");
+        let first = StartupLock::acquire(&socket).await.unwrap();
+
+        let waiting = tokio::spawn({
+            let socket = socket.clone();
+            async move { StartupLock::acquire(&socket).await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(5), waiting).await.unwrap().unwrap().unwrap();
+    }
+
+    #[test]
+    fn socket_names_are_stable_per_solution() {
+        assert_eq!(fnv1a("file:///work/App.sln"), fnv1a("file:///work/App.sln"));
+        assert_ne!(fnv1a("file:///work/App.sln"), fnv1a("file:///work/Other.sln"));
+    }
+}
@@ -8,6 +8,13 @@ use zip::ZipArchive;
 // Use stable version from nuget.org (public, no authentication required)
 const ROSLYN_VERSION: &str = "5.0.0-1.25277.114";
 
+// Razor extension built alongside ROSLYN_VERSION; Roslyn loads it for .razor/.cshtml files
+const RAZOR_PACKAGE: &str = "Microsoft.VisualStudioCode.RazorExtension";
+const RAZOR_VERSION: &str = "10.0.0-preview.25277.114";
+
+// NuGet feed both packages are published to
+const NUGET_FEED: &str = "https://pkgs.dev.azure.com/azure-public/vside/_packaging/msft_consumption/nuget/v3/flat2";
+
 // LSP Message Type Constants
 const LSP_MESSAGE_TYPE_INFO: i64 = 3;
 
@@ -210,37 +217,7 @@ async fn download_and_extract_roslyn(target_dir: &Path, version: &str) -> Result
 
     let rid = get_platform_rid();
     let package_name = format!("Microsoft.CodeAnalysis.LanguageServer.{rid}");
-
-    // Use Azure DevOps NuGet v3 flat container URL (lowercase package name)
-    let package_name_lower = package_name.to_lowercase();
-    let nuget_url = format!(
-        "https://pkgs.dev.azure.com/azure-public/vside/_packaging/msft_consumption/nuget/v3/flat2/{package_name_lower}/{version}/{package_name_lower}.{version}.nupkg"
-    );
-
-    crate::logger::debug(format!("[roslyn_wrapper] Download URL: {nuget_url}"));
-
-    let client = reqwest::Client::new();
-    let response = client.get(&nuget_url).send().await.map_err(|e| {
-        let error_msg = format!("Network error downloading Roslyn: {}", e);
-        send_lsp_notification(&error_msg);
-        anyhow!(error_msg)
-    })?;
-
-    if !response.status().is_success() {
-        let error_msg = format!(
-            "Failed to download Roslyn {}: HTTP {}",
-            version,
-            response.status()
-        );
-        send_lsp_notification(&error_msg);
-        return Err(anyhow!(error_msg));
-    }
-
-    let bytes = response.bytes().await?;
-    crate::logger::debug(format!(
-        "[roslyn_wrapper] Download size {} bytes",
-        bytes.len()
-    ));
+    let bytes = download_package("Roslyn", &package_name, version).await?;
 
     send_lsp_notification("Extracting Roslyn LSP...");
 
@@ -283,6 +260,147 @@ async fn download_and_extract_roslyn(target_dir: &Path, version: &str) -> Result
     Ok(())
 }
 
+/// Download a package from the NuGet feed; `label` names it in error messages
+async fn download_package(label: &str, package_name: &str, version: &str) -> Result<bytes::Bytes> {
+    // Use Azure DevOps NuGet v3 flat container URL (lowercase package name)
+    let package_name_lower = package_name.to_lowercase();
+    let nuget_url = format!("{NUGET_FEED}/{package_name_lower}/{version}/{package_name_lower}.{version}.nupkg");
+
+    crate::logger::debug(format!("[roslyn_wrapper] Download URL: {nuget_url}"));
+
+    let client = reqwest::Client::new();
+    let response = client.get(&nuget_url).send().await.map_err(|e| {
+        let error_msg = format!("Network error downloading {label}: {e}");
+        send_lsp_notification(&error_msg);
+        anyhow!(error_msg)
+    })?;
+
+    if !response.status().is_success() {
+        let error_msg = format!("Failed to download {label} {version}: HTTP {}", response.status());
+        send_lsp_notification(&error_msg);
+        return Err(anyhow!(error_msg));
+    }
+
+    let bytes = response.bytes().await?;
+    crate::logger::debug(format!(
+        "[roslyn_wrapper] Download size {} bytes",
+        bytes.len()
+    ));
+    Ok(bytes)
+}
+
+/// Razor assets Roslyn loads to handle `.razor` and `.cshtml` files (Razor cohosting)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RazorAssets {
+    /// The Razor source generator (`--razorSourceGenerator`)
+    pub source_generator: PathBuf,
+    /// Design-time targets for Razor projects (`--razorDesignTimePath`)
+    pub design_time_targets: PathBuf,
+    /// Roslyn extension with the Razor language features (`--extension`)
+    pub extension: PathBuf,
+}
+
+impl RazorAssets {
+    /// Locate the assets in an extracted Razor extension package
+    fn find(dir: &Path) -> Result<Self> {
+        let find = |name: &str| {
+            walkdir::WalkDir::new(dir)
+                .into_iter()
+                .flatten()
+                .find(|entry| entry.file_name() == name)
+                .map(|entry| entry.into_path())
+                .ok_or_else(|| anyhow!("{name} not found in {}", dir.display()))
+        };
+        Ok(Self {
+            source_generator: find("Microsoft.CodeAnalysis.Razor.Compiler.dll")?,
+            design_time_targets: find("Microsoft.NET.Sdk.Razor.DesignTime.targets")?,
+            extension: find("Microsoft.VisualStudioCode.RazorExtension.dll")?,
+        })
+    }
+
+    /// Arguments that make Roslyn load the assets
+    pub fn roslyn_args(&self) -> Vec<String> {
+        let path = |path: &PathBuf| path.to_string_lossy().into_owned();
+        vec![
+            "--razorSourceGenerator".to_string(),
+            path(&self.source_generator),
+            "--razorDesignTimePath".to_string(),
+            path(&self.design_time_targets),
+            "--extension".to_string(),
+            path(&self.extension),
+        ]
+    }
+}
+
+/// Get the Razor assets for the Roslyn at `roslyn`. For the downloaded Roslyn they are
+/// downloaded next to it if needed; any other Roslyn may be a different build, so only
+/// assets shipped alongside it are used.
+pub async fn get_razor_assets(roslyn: &Path) -> Result<RazorAssets> {
+    let version_dir = get_cache_dir()?.join(ROSLYN_VERSION);
+    if !roslyn.starts_with(&version_dir) {
+        let dir = roslyn.parent().unwrap_or(roslyn);
+        return RazorAssets::find(dir).map_err(|e| {
+            let roslyn = roslyn.display();
+            anyhow!("{roslyn} is not the downloaded Roslyn {ROSLYN_VERSION} and has no Razor assets next to it ({e})")
+        });
+    }
+    let razor_dir = version_dir.join(format!("razor-{RAZOR_VERSION}"));
+    if let Ok(assets) = RazorAssets::find(&razor_dir) {
+        crate::logger::info(format!("[roslyn_wrapper] Using cached Razor {RAZOR_VERSION}"));
+        return Ok(assets);
+    }
+
+    send_lsp_notification(&format!("Downloading Razor support {RAZOR_VERSION}..."));
+    crate::logger::info(format!("[roslyn_wrapper] Downloading Razor {RAZOR_VERSION}"));
+    let bytes = download_package("Razor", RAZOR_PACKAGE, RAZOR_VERSION).await?;
+
+    // Extract next to the final location, then move it into place in one step
+    let parent = razor_dir
+        .parent()
+        .ok_or_else(|| anyhow!("Failed to get parent directory of {}", razor_dir.display()))?;
+    fs::create_dir_all(parent)?;
+    let temp_path = parent.join(format!(".tmp_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_path)?;
+    if let Err(e) = extract_package_content(&bytes, &temp_path) {
+        let _ = fs::remove_dir_all(&temp_path);
+        return Err(e);
+    }
+    let _ = fs::remove_dir_all(&razor_dir);
+    fs::rename(&temp_path, &razor_dir)?;
+
+    let assets = RazorAssets::find(&razor_dir)?;
+    crate::logger::info(format!("[roslyn_wrapper] Installed Razor {RAZOR_VERSION}"));
+    send_lsp_notification("Razor support installation complete");
+    Ok(assets)
+}
+
+/// Extract a package's files, dropping NuGet metadata and the `content/` prefix
+fn extract_package_content(bytes: &[u8], target: &Path) -> Result<()> {
+    let mut zip = ZipArchive::new(std::io::Cursor::new(bytes))?;
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        // `enclosed_name` rejects entries that would escape the target directory
+        let Some(name) = file.enclosed_name().map(Path::to_path_buf) else {
+            continue;
+        };
+        let relative = name.strip_prefix("content").unwrap_or(&name);
+        let metadata = name.starts_with("_rels")
+            || name.starts_with("package")
+            || name.extension().is_some_and(|ext| ext == "nuspec")
+            || name == Path::new("[Content_Types].xml");
+        if file.is_dir() || metadata || relative.as_os_str().is_empty() {
+            continue;
+        }
+        let target_file_path = target.join(relative);
+        if let Some(parent) = target_file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut target_file = fs::File::create(&target_file_path)?;
+        std::io::copy(&mut file, &mut target_file)?;
+    }
+    Ok(())
+}
+
 /// Extract a ZIP archive and copy LanguageServer files to temp directory
 fn extract_zip(bytes: &[u8], temp_path: &Path) -> Result<()> {
     let mut zip = ZipArchive::new(std::io::Cursor::new(bytes))?;
@@ -362,4 +480,53 @@ mod tests {
         assert!(!rid.is_empty());
         println!("Platform RID: {rid}");
     }
+
+    #[test]
+    fn test_razor_version_matches_roslyn_build() {
+        // Both packages come out of the same build; a mismatched pair fails to load the extension
+        let build = |version: &'static str| version.split('.').rev().take(2).collect::<Vec<_>>();
+        assert_eq!(build(RAZOR_VERSION), build(ROSLYN_VERSION));
+    }
+
+    #[test]
+    fn test_razor_assets_for_another_roslyn_come_from_its_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let roslyn = dir.path().join("Microsoft.CodeAnalysis.LanguageServer");
+        let err = tokio_test::block_on(get_razor_assets(&roslyn)).unwrap_err();
+        assert!(err.to_string().contains("no Razor assets next to it"));
+
+        for file in [
+            "Microsoft.CodeAnalysis.Razor.Compiler.dll",
+            "Targets/Microsoft.NET.Sdk.Razor.DesignTime.targets",
+            "Microsoft.VisualStudioCode.RazorExtension.dll",
+        ] {
+            let path = dir.path().join(".razor").join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        let assets = tokio_test::block_on(get_razor_assets(&roslyn)).unwrap();
+        assert!(assets.extension.starts_with(dir.path()));
+    }
+
+    #[test]
+    fn test_razor_assets_from_extracted_package() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(RazorAssets::find(dir.path()).is_err());
+
+        for file in [
+            "Microsoft.CodeAnalysis.Razor.Compiler.dll",
+            "Targets/Microsoft.NET.Sdk.Razor.DesignTime.targets",
+            "Microsoft.VisualStudioCode.RazorExtension.dll",
+        ] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        let assets = RazorAssets::find(dir.path()).unwrap();
+        assert!(assets.design_time_targets.ends_with("Targets/Microsoft.NET.Sdk.Razor.DesignTime.targets"));
+        let args = assets.roslyn_args();
+        assert_eq!(args[0], "--razorSourceGenerator");
+        assert_eq!(args[4], "--extension");
+        assert!(args[5].ends_with("Microsoft.VisualStudioCode.RazorExtension.dll"));
+    }
 }
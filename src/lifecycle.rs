@@ -0,0 +1,238 @@
+// Roslyn process lifecycle: tying it to the wrapper and the editor, waiting for
+// it to exit after `shutdown`/`exit`, killing the whole process tree when it
+// doesn't, and turning termination signals into a graceful shutdown of the session.
+use std::io;
+use std::time::Duration;
+
+use tokio::process::{Child, Command};
+
+use crate::logger;
+
+/// How long Roslyn gets to exit on its own after `exit` before it is killed
+pub const ROSLYN_EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How often the editor process named in `initialize` is checked
+const PARENT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Start Roslyn in its own process group so its BuildHost children can be killed
+/// along with it, and on Linux have the kernel kill it if the wrapper dies.
+///
+/// The parent-death signal fires when the spawning *thread* exits, so the command
+/// must be spawned from a thread that lives as long as the wrapper (the main one).
+pub fn bind_to_wrapper(command: &mut Command) {
+    #[cfg(unix)]
+    command.process_group(0);
+
+    #[cfg(target_os = "linux")]
+    {
+        let wrapper = std::process::id();
+        // SAFETY: only async-signal-safe calls between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                // The wrapper may have died before the signal was armed
+                if libc::getppid() as u32 != wrapper {
+                    return Err(io::Error::other("wrapper exited while starting Roslyn"));
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+/// Resolve once process `pid` no longer exists
+pub async fn process_exited(pid: u32) {
+    let mut poll = tokio::time::interval(PARENT_POLL_INTERVAL);
+    while process_alive(pid) {
+        poll.tick().await;
+    }
+}
+
+/// Whether `pid` names a running process visible to the wrapper
+#[cfg(unix)]
+pub fn process_alive(pid: u32) -> bool {
+    // Larger values would address process groups instead
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+    // SAFETY: signal 0 only checks that the process exists
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether `pid` names a running process visible to the wrapper
+#[cfg(windows)]
+pub fn process_alive(pid: u32) -> bool {
+    use windows_sys::Win32::Foundation::{CloseHandle, STILL_ACTIVE};
+    use windows_sys::Win32::System::Threading::{
+        GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    // SAFETY: the handle is checked before use and closed afterwards
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return false;
+        }
+        let mut code = 0u32;
+        let queried = GetExitCodeProcess(handle, &mut code);
+        CloseHandle(handle);
+        queried != 0 && code == STILL_ACTIVE as u32
+    }
+}
+
+/// Wait for Roslyn to exit, killing its process tree after `timeout`.
+///
+/// Returns whether it exited by itself with a success status.
+pub async fn wait_or_kill(child: &mut Child, timeout: Duration) -> bool {
+    match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(Ok(status)) => {
+            logger::info(format!("[roslyn_wrapper] Roslyn exited ({status})"));
+            status.success()
+        }
+        Ok(Err(e)) => {
+            logger::error(format!("[roslyn_wrapper] Error waiting for Roslyn: {e}"));
+            false
+        }
+        Err(_) => {
+            logger::error(format!(
+                "[roslyn_wrapper] Roslyn did not exit within {}s; killing its process tree",
+                timeout.as_secs()
+            ));
+            if let Some(pid) = child.id() {
+                if let Err(e) = kill_tree(pid) {
+                    logger::error(format!("[roslyn_wrapper] Error killing Roslyn process tree: {e}"));
+                }
+            }
+            // Make sure the direct child is gone even if the tree walk failed
+            let _ = child.kill().await;
+            false
+        }
+    }
+}
+
+/// Kill `pid` and every process descended from it
+#[cfg(unix)]
+fn kill_tree(pid: u32) -> io::Result<()> {
+    // Collect the tree first: once the parent dies its children are re-parented
+    let mut tree = vec![pid];
+    let table = process_table()?;
+    let mut i = 0;
+    while i < tree.len() {
+        let parent = tree[i];
+        tree.extend(table.iter().filter(|(_, ppid)| *ppid == parent).map(|(pid, _)| *pid));
+        i += 1;
+    }
+
+    // SAFETY: plain syscalls; a pid or group that already exited only yields ESRCH
+    unsafe {
+        // Roslyn leads its own process group, which also catches re-parented grandchildren
+        libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        for pid in tree {
+            libc::kill(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+    Ok(())
+}
+
+/// `(pid, ppid)` of every running process
+#[cfg(unix)]
+fn process_table() -> io::Result<Vec<(u32, u32)>> {
+    let output = std::process::Command::new("ps")
+        .args(["-A", "-o", "pid=,ppid="])
+        .output()?;
+    Ok(parse_process_table(&String::from_utf8_lossy(&output.stdout)))
+}
+
+#[cfg(unix)]
+fn parse_process_table(ps_output: &str) -> Vec<(u32, u32)> {
+    ps_output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let pid = fields.next()?.parse().ok()?;
+            let ppid = fields.next()?.parse().ok()?;
+            Some((pid, ppid))
+        })
+        .collect()
+}
+
+/// Kill `pid` and every process descended from it
+#[cfg(windows)]
+fn kill_tree(pid: u32) -> io::Result<()> {
+    let status = std::process::Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/T", "/F"])
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("taskkill failed ({status})")));
+    }
+    Ok(())
+}
+
+/// Resolve once the wrapper is asked to terminate (SIGTERM or SIGINT / Ctrl+C)
+pub async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => tokio::select! {
+                _ = sigterm.recv() => "SIGTERM",
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+            },
+            Err(e) => {
+                logger::error(format!("[roslyn_wrapper] Cannot watch for SIGTERM: {e}"));
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl+C"
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process::Stdio;
+
+    #[test]
+    fn process_table_parsing_skips_junk() {
+        let table = parse_process_table("    1     0\n  42    1\nnot a line\n 43 42\n");
+        assert_eq!(table, [(1, 0), (42, 1), (43, 42)]);
+    }
+
+    #[tokio::test]
+    async fn exited_process_is_noticed() {
+        let mut child = tokio::process::Command::new("sleep").arg("0.2").spawn().unwrap();
+        let pid = child.id().unwrap();
+        assert!(process_alive(pid));
+        // Reap it, as init would for a dead editor, so no zombie is left behind
+        tokio::spawn(async move { child.wait().await });
+        tokio::time::timeout(Duration::from_secs(5), process_exited(pid))
+            .await
+            .expect("exit not noticed");
+        assert!(!process_alive(0));
+        assert!(!process_alive(u32::MAX));
+    }
+
+    #[tokio::test]
+    async fn stubborn_process_tree_is_killed() {
+        // A shell that ignores the exit request and has a child of its own
+        let mut child = tokio::process::Command::new("sh")
+            .args(["-c", "sleep 30 & wait"])
+            .stdin(Stdio::null())
+            .spawn()
+            .unwrap();
+        let clean = wait_or_kill(&mut child, Duration::from_millis(200)).await;
+        assert!(!clean);
+        assert!(child.try_wait().unwrap().is_some());
+    }
+}
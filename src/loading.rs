@@ -0,0 +1,276 @@
+// Solution loading: the "Loading solution…" progress shown while Roslyn loads the
+// solution from `solution/open`, and the optional queue that holds requests which
+// would only return partial results until `workspace/projectInitializationComplete`.
+//
+// Roslyn only reports individual projects through log lines, so the progress is
+// derived from `window/logMessage` and the project count from the solution file.
+use std::collections::HashSet;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::{logger, lsp, path_utils};
+
+/// Progress token for the solution load
+pub const PROGRESS_TOKEN: &str = "roslyn-wrapper/solution-load";
+
+// Requests that come back empty or partial until every project is loaded
+const DEFAULT_HELD_METHODS: &[&str] = &[
+    "textDocument/definition",
+    "textDocument/typeDefinition",
+    "textDocument/implementation",
+    "textDocument/references",
+    "workspace/symbol",
+];
+
+// How long a held request waits before it is forwarded anyway
+pub const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(120);
+
+/// Which requests to hold while the solution loads, configured through
+/// `initializationOptions.holdRequestsUntilLoaded`: `true` for the defaults, or
+/// `{ "methods": [...], "maxWait": 120 }` with the wait in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HoldRequests {
+    pub methods: HashSet<String>,
+    pub max_wait: Duration,
+}
+
+impl HoldRequests {
+    /// Parse the option; `None` leaves requests alone
+    pub fn from_option(option: &Value) -> Option<Self> {
+        let defaults = || Self {
+            methods: DEFAULT_HELD_METHODS.iter().map(|m| m.to_string()).collect(),
+            max_wait: DEFAULT_MAX_WAIT,
+        };
+        match option {
+            Value::Bool(true) => Some(defaults()),
+            Value::Object(options) => {
+                let mut hold = defaults();
+                if let Some(methods) = options.get("methods").and_then(Value::as_array) {
+                    hold.methods = methods.iter().filter_map(Value::as_str).map(String::from).collect();
+                }
+                if let Some(seconds) = options.get("maxWait").and_then(Value::as_f64) {
+                    match Duration::try_from_secs_f64(seconds) {
+                        Ok(max_wait) => hold.max_wait = max_wait,
+                        Err(_) if seconds.is_finite() && seconds > 0.0 => logger::warn(
+                            "[roslyn_wrapper] holdRequestsUntilLoaded.maxWait is out of range; using the default",
+                        ),
+                        Err(_) => logger::warn("[roslyn_wrapper] Ignoring holdRequestsUntilLoaded.maxWait: expected seconds"),
+                    }
+                }
+                Some(hold)
+            }
+            Value::Bool(false) | Value::Null => None,
+            _ => {
+                logger::warn("[roslyn_wrapper] Ignoring holdRequestsUntilLoaded: expected true or an object");
+                None
+            }
+        }
+    }
+}
+
+/// Project counts for the load indicator, fed from Roslyn's log lines
+#[derive(Debug, Default)]
+pub struct LoadProgress {
+    // Projects listed in the solution file, if it could be read
+    expected: usize,
+    started: HashSet<String>,
+    loaded: HashSet<String>,
+    current: Option<String>,
+}
+
+impl LoadProgress {
+    /// Start counting for the solution or project at `uri`
+    pub fn for_solution(uri: &str) -> Self {
+        Self {
+            expected: count_projects(uri),
+            ..Self::default()
+        }
+    }
+
+    /// Apply one `window/logMessage` line; returns whether the progress changed
+    pub fn observe(&mut self, message: &str) -> bool {
+        // Lines carry the logging category: "[LanguageServerProjectSystem] Loading ..."
+        let line = message.trim();
+        let line = match line.strip_prefix('[').and_then(|rest| rest.split_once("] ")) {
+            Some((_, rest)) => rest,
+            None => line,
+        };
+        if let Some(project) = line.strip_prefix("Loading ").and_then(|rest| rest.strip_suffix("...")) {
+            if !is_project_file(project) {
+                return false;
+            }
+            self.started.insert(project.to_string());
+            self.current = Some(project.to_string());
+            return true;
+        }
+        if let Some(project) = line.strip_prefix("Successfully completed load of ") {
+            self.started.insert(project.to_string());
+            return self.loaded.insert(project.to_string());
+        }
+        false
+    }
+
+    pub fn loaded(&self) -> usize {
+        self.loaded.len()
+    }
+
+    pub fn total(&self) -> usize {
+        self.expected.max(self.started.len())
+    }
+
+    /// Only known when the solution file listed the projects; a total that grows
+    /// while loading would make the percentage go backwards
+    fn percentage(&self) -> Option<usize> {
+        (self.expected > 0).then(|| (self.loaded() * 100 / self.total()).min(100))
+    }
+}
+
+/// Display name of the solution or project being loaded
+pub fn solution_name(uri: &str) -> &str {
+    uri.rsplit(['/', '\\']).next().unwrap_or(uri)
+}
+
+/// Project name without directory or extension
+fn project_name(path: &str) -> &str {
+    let file = solution_name(path);
+    file.rsplit_once('.').map_or(file, |(stem, _)| stem)
+}
+
+fn is_project_file(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    [".csproj", ".vbproj", ".fsproj"].iter().any(|ext| path.ends_with(ext))
+}
+
+/// Number of projects in the `.sln`/`.slnx` at `uri`, 1 for a project file, or 0 if unknown
+fn count_projects(uri: &str) -> usize {
+    if is_project_file(uri) {
+        return 1;
+    }
+    let text = path_utils::url_to_path(uri)
+        .ok()
+        .and_then(|path| std::fs::read_to_string(path).ok());
+    text.map_or(0, |text| count_solution_projects(&text))
+}
+
+fn count_solution_projects(solution: &str) -> usize {
+    // .sln:  Project("{type}") = "App", "App\App.csproj", "{id}"  (folders have no project file)
+    // .slnx: <Project Path="App/App.csproj" />
+    solution
+        .lines()
+        .map(str::trim_start)
+        .filter(|line| line.starts_with("Project(") || line.starts_with("<Project "))
+        .filter(|line| line.split('"').any(is_project_file))
+        .count()
+}
+
+/// How the load went, for the log and the end of the indicator
+pub fn load_summary(progress: &LoadProgress, elapsed: Duration) -> String {
+    match progress.loaded() {
+        0 => format!("Loaded in {:.1}s", elapsed.as_secs_f64()),
+        1 => format!("Loaded 1 project in {:.1}s", elapsed.as_secs_f64()),
+        n => format!("Loaded {n} projects in {:.1}s", elapsed.as_secs_f64()),
+    }
+}
+
+/// `$/progress` notification starting the load indicator
+pub fn progress_begin(solution: &str, load: &LoadProgress) -> Value {
+    let mut begin = json!({
+        "kind": "begin",
+        "title": "Loading solution…",
+        "message": solution_name(solution),
+        "cancellable": false
+    });
+    if let Some(percentage) = load.percentage() {
+        begin["percentage"] = json!(percentage);
+    }
+    progress(begin)
+}
+
+/// `$/progress` notification with the project counts so far
+pub fn progress_report(load: &LoadProgress) -> Value {
+    let mut message = format!("{}/{} projects", load.loaded(), load.total());
+    if let Some(current) = &load.current {
+        message = format!("{message} · {}", project_name(current));
+    }
+    let mut report = json!({ "kind": "report", "message": message });
+    if let Some(percentage) = load.percentage() {
+        report["percentage"] = json!(percentage);
+    }
+    progress(report)
+}
+
+/// `$/progress` notification ending the load indicator
+pub fn progress_end(message: &str) -> Value {
+    progress(json!({ "kind": "end", "message": message }))
+}
+
+fn progress(value: Value) -> Value {
+    lsp::progress(PROGRESS_TOKEN, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hold_option_accepts_flag_or_object() {
+        assert_eq!(HoldRequests::from_option(&json!(false)), None);
+        assert_eq!(HoldRequests::from_option(&json!("yes")), None);
+
+        let defaults = HoldRequests::from_option(&json!(true)).unwrap();
+        assert!(defaults.methods.contains("textDocument/definition"));
+        assert_eq!(defaults.max_wait, DEFAULT_MAX_WAIT);
+
+        let custom = HoldRequests::from_option(&json!({ "methods": ["workspace/symbol"], "maxWait": 30 })).unwrap();
+        assert_eq!(custom.methods, HashSet::from(["workspace/symbol".to_string()]));
+        assert_eq!(custom.max_wait, Duration::from_secs(30));
+
+        let huge = HoldRequests::from_option(&json!({ "maxWait": 1e300 })).unwrap();
+        assert_eq!(huge.max_wait, DEFAULT_MAX_WAIT);
+    }
+
+    #[test]
+    fn solution_name_is_the_last_path_segment() {
+        assert_eq!(solution_name("file:///work/App/App.sln"), "App.sln");
+        assert_eq!(solution_name("App.csproj"), "App.csproj");
+        assert_eq!(project_name("C:\\work\\App.Core\\App.Core.csproj"), "App.Core");
+    }
+
+    #[test]
+    fn solution_files_list_their_projects() {
+        let sln = r#"
+Project("{FAE04EC0-301F-11D3-BF4B-00C04F79EFBC}") = "App", "App\App.csproj", "{1}"
+EndProject
+Project("{2150E333-8FDC-42A3-9474-1A3956D46DE8}") = "src", "src", "{2}"
+EndProject
+Project("{FAE04EC0-301F-11D3-BF4B-00C04F79EFBC}") = "App.Tests", "tests\App.Tests.csproj", "{3}"
+EndProject
+"#;
+        assert_eq!(count_solution_projects(sln), 2);
+        let slnx = r#"<Solution>
+  <Folder Name="/src/">
+    <Project Path="src/App/App.csproj" />
+  </Folder>
+  <Project Path="tests/App.Tests/App.Tests.fsproj" />
+</Solution>"#;
+        assert_eq!(count_solution_projects(slnx), 2);
+    }
+
+    #[test]
+    fn progress_follows_project_load_log_lines() {
+        let mut load = LoadProgress { expected: 2, ..LoadProgress::default() };
+        assert!(load.observe("[LanguageServerProjectSystem] Loading /work/App/App.csproj..."));
+        assert!(!load.observe("[LanguageServerProjectSystem] Loading /work/App.sln..."));
+        assert!(!load.observe("Some unrelated message"));
+        assert!(load.observe("Successfully completed load of /work/App/App.csproj"));
+        assert!(!load.observe("Successfully completed load of /work/App/App.csproj"));
+
+        let report = progress_report(&load);
+        assert_eq!(report["params"]["value"]["message"], "1/2 projects · App");
+        assert_eq!(report["params"]["value"]["percentage"], 50);
+        let unknown_total = LoadProgress { started: load.started.clone(), ..LoadProgress::default() };
+        assert!(progress_report(&unknown_total)["params"]["value"].get("percentage").is_none());
+        assert_eq!(load_summary(&load, Duration::from_millis(2500)), "Loaded 1 project in 2.5s");
+    }
+}
@@ -0,0 +1,209 @@
+// `tracing` and the `log` facade, routed into the logger.
+//
+// The wrapper logs through `tracing` under the `roslyn_wrapper` target, and so do
+// dependencies built on it; crates using `log` come in through the facade. All of
+// them end up in the same file, filtered by target: dependencies only log warnings
+// unless `logLevels` says otherwise. Span storage is `tracing-subscriber`'s registry;
+// `LoggerLayer` keeps each span's fields next to it, and entries logged inside a span
+// carry them, such as the method and id of an LSP request.
+use serde_json::{json, Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Registry;
+
+use crate::logger::{self, LogLevel};
+
+/// Where the layer hands entries: target, level, message and context
+type Sink = fn(&str, LogLevel, &str, Map<String, Value>);
+
+/// Route `tracing` events and `log` records into the logger; called once at startup.
+/// Without it the wrapper's own entries still reach the logger, without span context.
+pub fn install() {
+    let _ = tracing::subscriber::set_global_default(subscriber(logger::dispatch));
+    if log::set_logger(&LOG_FACADE).is_ok() {
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+}
+
+fn subscriber(sink: Sink) -> impl Subscriber + Send + Sync {
+    Registry::default().with(LoggerLayer { sink })
+}
+
+fn level(level: &Level) -> LogLevel {
+    match *level {
+        Level::ERROR => LogLevel::Error,
+        Level::WARN => LogLevel::Warn,
+        Level::INFO => LogLevel::Info,
+        Level::DEBUG => LogLevel::Debug,
+        _ => LogLevel::Trace,
+    }
+}
+
+struct LoggerLayer {
+    sink: Sink,
+}
+
+impl<S> Layer<S> for LoggerLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn register_callsite(&self, _: &'static Metadata<'static>) -> Interest {
+        // Levels change with the configuration, so ask `enabled` every time
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, _: Context<'_, S>) -> bool {
+        // A span matters as long as its target logs anything: even errors carry it
+        let level = match metadata.is_span() {
+            true => LogLevel::Error,
+            false => level(metadata.level()),
+        };
+        logger::wants(metadata.target(), level)
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let message = match fields.0.remove("message") {
+            Some(Value::String(message)) => message,
+            Some(other) => other.to_string(),
+            None => String::new(),
+        };
+        let mut context = fields.0;
+        let spans: Vec<Value> = ctx
+            .event_scope(event)
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .map(|span| {
+                let mut fields = span.extensions().get::<Fields>().map(|f| f.0.clone()).unwrap_or_default();
+                fields.insert("span".to_string(), json!(span.name()));
+                Value::Object(fields)
+            })
+            .collect();
+        if !spans.is_empty() {
+            context.insert("spans".to_string(), Value::Array(spans));
+        }
+        let metadata = event.metadata();
+        (self.sink)(metadata.target(), level(metadata.level()), &message, context);
+    }
+}
+
+/// Event and span fields as JSON values
+#[derive(Default)]
+struct Fields(Map<String, Value>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(field.name().to_string(), json!(format!("{value:?}")));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+}
+
+/// Records from crates that use `log` rather than `tracing`
+struct LogFacade;
+
+static LOG_FACADE: LogFacade = LogFacade;
+
+fn log_level(level: log::Level) -> LogLevel {
+    match level {
+        log::Level::Error => LogLevel::Error,
+        log::Level::Warn => LogLevel::Warn,
+        log::Level::Info => LogLevel::Info,
+        log::Level::Debug => LogLevel::Debug,
+        log::Level::Trace => LogLevel::Trace,
+    }
+}
+
+impl log::Log for LogFacade {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        logger::wants(metadata.target(), log_level(metadata.level()))
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if self.enabled(record.metadata()) {
+            let message = record.args().to_string();
+            logger::dispatch(record.target(), log_level(record.level()), &message, Map::new());
+        }
+    }
+
+    fn flush(&self) {
+        logger::flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    static ENTRIES: Mutex<Vec<(String, Map<String, Value>)>> = Mutex::new(Vec::new());
+
+    fn collect(_: &str, _: LogLevel, message: &str, context: Map<String, Value>) {
+        ENTRIES.lock().unwrap().push((message.to_string(), context));
+    }
+
+    #[test]
+    fn entries_carry_the_fields_of_the_spans_they_are_in() {
+        tracing::subscriber::with_default(subscriber(collect), || {
+            let request = tracing::info_span!(target: "roslyn_wrapper", "request", method = "textDocument/hover", id = 3);
+            {
+                let _entered = request.enter();
+                let restore = tracing::info_span!(target: "roslyn_wrapper", "restore", project = "App.csproj");
+                let _restore = restore.enter();
+                tracing::warn!(target: "roslyn_wrapper", attempt = 2, "Retrying");
+            }
+            tracing::info!(target: "roslyn_wrapper", "Outside");
+        });
+
+        let entries = std::mem::take(&mut *ENTRIES.lock().unwrap());
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, "Retrying");
+        assert_eq!(
+            Value::Object(entries[0].1.clone()),
+            json!({
+                "attempt": 2,
+                "spans": [
+                    { "span": "request", "method": "textDocument/hover", "id": 3 },
+                    { "span": "restore", "project": "App.csproj" },
+                ]
+            })
+        );
+        assert_eq!(entries[1].0, "Outside");
+        assert!(entries[1].1.is_empty());
+    }
+}
@@ -1,46 +1,434 @@
-// Logging module controlled by LSP initialization options.
-// Defaults: level=info, file=./roslyn_wrapper.log unless reconfigured at runtime.
-use chrono::{Local, SecondsFormat};
+// Logging module controlled by LSP initialization options. The wrapper's own entries,
+// Roslyn's output and the protocol traffic go to a rotated log file, and optionally to
+// the editor. Logging never waits for the file: a writer thread drains a bounded queue.
+use chrono::{DateTime, Local, SecondsFormat};
+use directories::ProjectDirs;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use once_cell::sync::Lazy;
+use serde_json::{json, Map, Value};
+use std::fmt::Write as _;
+use std::collections::{HashMap, VecDeque};
 use std::fs::{File, OpenOptions};
 use std::io::Write;
-use std::path::PathBuf;
-use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
 
-static LOG_SINK: Lazy<Mutex<LogSink>> = Lazy::new(|| Mutex::new(LogSink::new(default_log_file_path())));
+use crate::lsp::{Message, LSP_MESSAGE_TYPE_ERROR, LSP_MESSAGE_TYPE_INFO, LSP_MESSAGE_TYPE_LOG, LSP_MESSAGE_TYPE_WARNING};
+
+// Tells apart the runs of the wrapper writing to the same file
+static SESSION_ID: Lazy<String> = Lazy::new(|| uuid::Uuid::new_v4().simple().to_string()[..8].to_string());
+
+// Records waiting for the writer thread; further ones are dropped and counted
+const LOG_QUEUE_CAPACITY: usize = 4096;
+// Records written between two flushes of the file
+const MAX_BATCH: usize = 256;
+// How long `flush` waits for the writer thread
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+static LOG_QUEUE: Lazy<LogQueue> = Lazy::new(|| {
+    let (queue, records, controls) = LogQueue::new(LOG_QUEUE_CAPACITY);
+    let dropped = queue.dropped.clone();
+    // Without the thread the queue fills up and records are dropped
+    let _ = std::thread::Builder::new()
+        .name("roslyn-wrapper-log".to_string())
+        .spawn(move || run_writer(LogSink::new(default_log_file_path()), records, controls, dropped));
+    queue
+});
+
+/// Target of the wrapper's own `tracing` events
+pub const WRAPPER_TARGET: &str = "roslyn_wrapper";
+
+// Effective level per `Source` and the format, read on every entry without locking
+static LEVELS: [AtomicU8; 4] = [
+    AtomicU8::new(LogLevel::Info as u8),
+    AtomicU8::new(LogLevel::Info as u8),
+    AtomicU8::new(LogLevel::Info as u8),
+    AtomicU8::new(LogLevel::Warn as u8),
+];
+// Dependency targets named in `logLevels`, longest first
+static TARGET_LEVELS: Lazy<RwLock<TargetLevels>> = Lazy::new(|| RwLock::new(Vec::new()));
+static JSON_FORMAT: AtomicBool = AtomicBool::new(false);
+// Set once the editor has no use for mirrored entries, so they skip the mirror's lock
+static MIRROR_OFF: AtomicBool = AtomicBool::new(false);
 
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
-enum LogLevel {
+pub enum LogLevel {
     Off = 0,
     Error = 1,
-    Info = 2,
-    Debug = 3,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl LogLevel {
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+/// Where a log entry comes from; each can have its own level (`logLevels`)
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum Source {
+    /// The wrapper's own entries
+    Wrapper,
+    /// Roslyn's stderr (and stdout once a pipe carries the protocol)
+    RoslynStderr,
+    /// The per-message traffic lines
+    Protocol,
+    /// Libraries logging through `tracing` or `log`, unless their target has a level
+    Dependencies,
+}
+
+impl Source {
+    const ALL: [Source; 4] = [Source::Wrapper, Source::RoslynStderr, Source::Protocol, Source::Dependencies];
+
+    fn name(self) -> &'static str {
+        match self {
+            Source::Wrapper => "wrapper",
+            Source::RoslynStderr => "roslyn-stderr",
+            Source::Protocol => "protocol",
+            Source::Dependencies => "dependencies",
+        }
+    }
+}
+
+/// `Json` writes one object per line, for log tooling
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+// Entries held back for the editor until its connection is up; older ones are dropped
+pub const MAX_PENDING_CLIENT_ENTRIES: usize = 200;
+
+static CLIENT_MIRROR: Lazy<Mutex<ClientMirror>> = Lazy::new(|| Mutex::new(ClientMirror::default()));
+
+/// Log entries on their way to the editor, as an LSP message type and text
+pub type ClientEntry = (i64, String);
+
+/// Wrapper log entries mirrored to the editor as `window/logMessage`
+#[derive(Default)]
+struct ClientMirror {
+    // `None` until `initialize` has said whether, and from which level, to mirror
+    level: Option<LogLevel>,
+    pending: VecDeque<(LogLevel, String)>,
+    sender: Option<mpsc::UnboundedSender<ClientEntry>>,
+    connected: bool,
+}
+
+impl ClientMirror {
+    fn push(&mut self, level: LogLevel, message: &str) {
+        if self.level.is_some_and(|max| level > max) {
+            return;
+        }
+        match (&self.sender, self.connected) {
+            (Some(sender), true) => {
+                let _ = sender.send(client_entry(level, message));
+            }
+            // Not decided yet, or the connection isn't up: keep it for later
+            _ => {
+                if self.pending.len() == MAX_PENDING_CLIENT_ENTRIES {
+                    self.pending.pop_front();
+                }
+                self.pending.push_back((level, message.to_string()));
+            }
+        }
+    }
+
+    fn connect(&mut self) {
+        let level = *self.level.get_or_insert(LogLevel::Off);
+        self.connected = true;
+        let pending = std::mem::take(&mut self.pending);
+        if let Some(sender) = &self.sender {
+            for (entry_level, message) in pending.iter().filter(|(l, _)| *l <= level) {
+                let _ = sender.send(client_entry(*entry_level, message));
+            }
+        }
+    }
+}
+
+fn client_entry(level: LogLevel, message: &str) -> ClientEntry {
+    let message_type = match level {
+        LogLevel::Error => LSP_MESSAGE_TYPE_ERROR,
+        LogLevel::Warn => LSP_MESSAGE_TYPE_WARNING,
+        LogLevel::Info => LSP_MESSAGE_TYPE_INFO,
+        _ => LSP_MESSAGE_TYPE_LOG,
+    };
+    (message_type, message.to_string())
+}
+
+// Rotation defaults: a new file every 10 MiB, keeping the last 5
+const DEFAULT_ROTATE_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_KEPT_FILES: usize = 5;
+
+/// When the log file is rotated and what is kept
+#[derive(Clone, Debug, PartialEq)]
+struct RotationOptions {
+    // `None` disables the size or age limit
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    /// Rotated files kept next to the log; older ones are deleted
+    max_files: usize,
+    /// Gzip rotated files (`roslyn_wrapper.log.1.gz`)
+    compress: bool,
+}
+
+impl Default for RotationOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: Some(DEFAULT_ROTATE_BYTES),
+            max_age: None,
+            max_files: DEFAULT_KEPT_FILES,
+            compress: false,
+        }
+    }
+}
+
+// Payloads longer than this are cut in the trace log unless configured otherwise
+pub const DEFAULT_TRACE_MAX_BYTES: usize = 64 * 1024;
+
+// Notifications that carry the full text of a document
+const DOCUMENT_TEXT_METHODS: &[&str] = &["textDocument/didOpen", "textDocument/didChange", "textDocument/didSave"];
+
+/// How payloads are captured at the trace level
+#[derive(Clone, Debug, PartialEq)]
+struct TraceOptions {
+    // `None` keeps payloads whole
+    max_bytes: Option<usize>,
+    redact_document_text: bool,
 }
 
 #[derive(Clone, Debug)]
 struct LogConfig {
     level: LogLevel,
+    // Per-source levels that replace `level`
+    overrides: HashMap<Source, LogLevel>,
+    format: LogFormat,
+    trace: TraceOptions,
+    rotation: RotationOptions,
     file_path: PathBuf,
 }
 
 static LOG_CONFIG: Lazy<Mutex<LogConfig>> = Lazy::new(|| Mutex::new(LogConfig {
     level: LogLevel::Info,
+    overrides: HashMap::new(),
+    format: LogFormat::Text,
+    trace: TraceOptions {
+        max_bytes: Some(DEFAULT_TRACE_MAX_BYTES),
+        redact_document_text: true,
+    },
+    rotation: RotationOptions::default(),
     file_path: default_log_file_path(),
 }));
 
+/// Which way a logged LSP message is travelling
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Direction {
+    FromClient,
+    ToRoslyn,
+    FromRoslyn,
+    ToClient,
+}
+
+impl Direction {
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::FromClient => "from_client",
+            Direction::ToRoslyn => "to_roslyn",
+            Direction::FromRoslyn => "from_roslyn",
+            Direction::ToClient => "to_client",
+        }
+    }
+
+    fn arrow(self) -> &'static str {
+        match self {
+            Direction::FromClient => "<== FROM CLIENT",
+            Direction::ToRoslyn => "==> TO ROSLYN",
+            Direction::FromRoslyn => "<== FROM ROSLYN",
+            Direction::ToClient => "==> TO CLIENT",
+        }
+    }
+}
+
 fn parse_level(s: &str) -> LogLevel {
     match s.to_lowercase().as_str() {
         "off" | "none" => LogLevel::Off,
         "error" => LogLevel::Error,
+        "warn" | "warning" => LogLevel::Warn,
         "info" => LogLevel::Info,
         "debug" => LogLevel::Debug,
+        "trace" => LogLevel::Trace,
         _ => LogLevel::Info,
     }
 }
 
-fn should_log(level: LogLevel) -> bool {
-    let cfg = LOG_CONFIG.lock().unwrap();
-    cfg.level >= level
+/// The format to write `level` in, or `None` if it is filtered out
+fn enabled(source: Source, level: LogLevel) -> Option<LogFormat> {
+    if LEVELS[source as usize].load(Ordering::Relaxed) < level as u8 {
+        return None;
+    }
+    match JSON_FORMAT.load(Ordering::Relaxed) {
+        true => Some(LogFormat::Json),
+        false => Some(LogFormat::Text),
+    }
+}
+
+impl LogConfig {
+    fn level_of(&self, source: Source) -> LogLevel {
+        match (self.overrides.get(&source), source) {
+            (Some(level), _) => *level,
+            // Libraries are chatty; only their warnings and errors by default
+            (None, Source::Dependencies) => self.level.min(LogLevel::Warn),
+            (None, _) => self.level,
+        }
+    }
+
+    /// Make the levels and format visible to `enabled`
+    fn publish(&self) {
+        for source in Source::ALL {
+            LEVELS[source as usize].store(self.level_of(source) as u8, Ordering::Relaxed);
+        }
+        JSON_FORMAT.store(self.format == LogFormat::Json, Ordering::Relaxed);
+    }
+}
+
+/// Payload capture at the trace level: `max_bytes` cuts payloads (0 keeps them whole),
+/// `redact_document_text` hides document contents in didOpen/didChange/didSave
+pub fn configure_trace(max_bytes: Option<u64>, redact_document_text: Option<bool>) {
+    let mut cfg = LOG_CONFIG.lock().unwrap();
+    if let Some(max_bytes) = max_bytes {
+        cfg.trace.max_bytes = usize::try_from(max_bytes).ok().filter(|max| *max > 0);
+    }
+    if let Some(redact) = redact_document_text {
+        cfg.trace.redact_document_text = redact;
+    }
+}
+
+/// Log rotation: by size (`max_bytes`) and age (`max_age_hours`), 0 disabling either;
+/// `max_files` rotated files are kept, gzipped when `compress` is set
+pub fn configure_rotation(
+    max_bytes: Option<u64>,
+    max_age_hours: Option<u64>,
+    max_files: Option<u64>,
+    compress: Option<bool>,
+) {
+    let mut cfg = LOG_CONFIG.lock().unwrap();
+    let rotation = &mut cfg.rotation;
+    if let Some(max_bytes) = max_bytes {
+        rotation.max_bytes = (max_bytes > 0).then_some(max_bytes);
+    }
+    if let Some(hours) = max_age_hours {
+        rotation.max_age = (hours > 0).then(|| Duration::from_secs(hours * 3600));
+    }
+    if let Some(max_files) = max_files {
+        rotation.max_files = usize::try_from(max_files).unwrap_or(usize::MAX);
+    }
+    if let Some(compress) = compress {
+        rotation.compress = compress;
+    }
+    let rotation = cfg.rotation.clone();
+    drop(cfg);
+    LOG_QUEUE.control(Control::Rotation(rotation));
+}
+
+/// Per-source levels from `logLevels`, e.g. `{"roslyn-stderr": "warn", "protocol": "trace"}`.
+/// Other keys name dependency targets, e.g. `{"reqwest": "debug"}`. Replaces the levels
+/// set by an earlier call.
+pub fn configure_sources(levels: &Value) {
+    let Some(levels) = levels.as_object() else {
+        warn("[roslyn_wrapper] Ignoring logLevels: expected an object");
+        return;
+    };
+    let (overrides, targets, ignored) = source_levels(levels);
+    {
+        let mut cfg = LOG_CONFIG.lock().unwrap();
+        cfg.overrides = overrides;
+        *TARGET_LEVELS.write().unwrap() = targets;
+        cfg.publish();
+    }
+    for name in ignored {
+        warn(format!("[roslyn_wrapper] Ignoring logLevels.{name}: expected a level"));
+    }
+}
+
+// Levels of dependency targets, e.g. `("hyper::proto", Off)`
+type TargetLevels = Vec<(String, LogLevel)>;
+
+/// Split `logLevels` into source levels and target levels (longest target first),
+/// plus the keys whose value is not a level
+fn source_levels(levels: &Map<String, Value>) -> (HashMap<Source, LogLevel>, TargetLevels, Vec<&str>) {
+    let mut overrides = HashMap::new();
+    let mut targets = Vec::new();
+    let mut ignored = Vec::new();
+    for (name, level) in levels {
+        let Some(level) = level.as_str().map(parse_level) else {
+            ignored.push(name.as_str());
+            continue;
+        };
+        match Source::ALL.into_iter().find(|s| s.name() == name) {
+            Some(source) => {
+                overrides.insert(source, level);
+            }
+            None => targets.push((name.clone(), level)),
+        }
+    }
+    targets.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+    (overrides, targets, ignored)
+}
+
+/// Mirror entries at or above `level` to the editor (`off` or `None`: don't).
+/// Called once `initialize` has arrived; entries are buffered until then.
+pub fn mirror_to_client(level: Option<&str>) {
+    let mut mirror = CLIENT_MIRROR.lock().unwrap();
+    let level = level.map_or(LogLevel::Off, parse_level);
+    mirror.level = Some(level);
+    MIRROR_OFF.store(level == LogLevel::Off, Ordering::Relaxed);
+    if level == LogLevel::Off {
+        mirror.pending.clear();
+    }
+}
+
+/// Where mirrored entries go; replaces an earlier receiver
+pub fn client_entries() -> mpsc::UnboundedReceiver<ClientEntry> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let mut mirror = CLIENT_MIRROR.lock().unwrap();
+    mirror.sender = Some(sender);
+    mirror.connected = false;
+    receiver
+}
+
+/// The editor connection is up: send what was held back, then entries as they come
+pub fn client_connected() {
+    CLIENT_MIRROR.lock().unwrap().connect();
+}
+
+/// Switch between `text` and `json` lines
+pub fn set_format(format: &str) {
+    let format = match format.to_lowercase().as_str() {
+        "text" => LogFormat::Text,
+        "json" => LogFormat::Json,
+        _ => {
+            error(format!("[roslyn_wrapper] Ignoring unknown logFormat: {format}"));
+            return;
+        }
+    };
+    let mut cfg = LOG_CONFIG.lock().unwrap();
+    cfg.format = format;
+    cfg.publish();
 }
 
 pub fn configure(level: Option<&str>, file_path: Option<&str>, directory: Option<&str>) {
@@ -60,89 +448,583 @@ pub fn configure(level: Option<&str>, file_path: Option<&str>, directory: Option
         }
     }
 
-    if let Ok(mut sink) = LOG_SINK.lock() {
-        sink.reopen(cfg.file_path.clone());
-        // Emit a line to confirm reconfiguration
-        let timestamp = Local::now().to_rfc3339_opts(SecondsFormat::Millis, true);
-        if let Some(f) = sink.file.as_mut() {
-            let _ = writeln!(
-                f,
-                "[{}] [roslyn_wrapper] Logger reconfigured (level: {:?}, path: {})",
-                timestamp,
-                cfg.level,
-                cfg.file_path.display()
-            );
-            let _ = f.flush();
+    cfg.publish();
+    let path = cfg.file_path.clone();
+    // Emit a line to confirm reconfiguration
+    let confirmation = format!(
+        "[roslyn_wrapper] Logger reconfigured (level: {:?}, path: {})",
+        cfg.level,
+        path.display()
+    );
+    drop(cfg);
+    LOG_QUEUE.control(Control::Reopen(path));
+    LOG_QUEUE.push(Record::Text(Local::now(), confirmation));
+}
+
+/// Write out everything logged so far; called before the process exits, and the only
+/// caller that waits for room in the queue
+pub fn flush() {
+    let (done, flushed) = std::sync::mpsc::channel();
+    if LOG_QUEUE.push_wait(Record::Flush(done)) {
+        let _ = flushed.recv_timeout(FLUSH_TIMEOUT);
+    }
+}
+
+fn log(level: LogLevel, message: &str) {
+    // No subscriber (`log_bridge::install` not called, as in tests and benches): log directly
+    if tracing::dispatcher::get_default(|dispatch| dispatch.is::<tracing::subscriber::NoSubscriber>()) {
+        log_from(Source::Wrapper, level, message);
+        return;
+    }
+    match level {
+        LogLevel::Off => {}
+        LogLevel::Error => tracing::error!(target: WRAPPER_TARGET, "{message}"),
+        LogLevel::Warn => tracing::warn!(target: WRAPPER_TARGET, "{message}"),
+        LogLevel::Info => tracing::info!(target: WRAPPER_TARGET, "{message}"),
+        LogLevel::Debug => tracing::debug!(target: WRAPPER_TARGET, "{message}"),
+        LogLevel::Trace => tracing::trace!(target: WRAPPER_TARGET, "{message}"),
+    }
+}
+
+fn is_wrapper_target(target: &str) -> bool {
+    target == WRAPPER_TARGET || target.starts_with("roslyn_wrapper::")
+}
+
+/// Whether the level for `target` lets an entry at `level` into the log
+fn target_allows(target: &str, level: LogLevel) -> bool {
+    if !is_wrapper_target(target) {
+        if let Some(named) = named_level(&TARGET_LEVELS.read().unwrap(), target) {
+            return named as u8 >= level as u8;
         }
+        return enabled(Source::Dependencies, level).is_some();
     }
+    enabled(Source::Wrapper, level).is_some()
 }
 
-pub fn log_line(message: impl AsRef<str>) {
-    if should_log(LogLevel::Info) {
-        if let Ok(mut sink) = LOG_SINK.lock() {
-            sink.write_str(message.as_ref());
+/// Level of the most specific entry in `targets` naming `target` or one of its parents
+fn named_level(targets: &[(String, LogLevel)], target: &str) -> Option<LogLevel> {
+    let named = targets.iter().find(|(prefix, _)| {
+        target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    });
+    named.map(|(_, level)| *level)
+}
+
+/// Whether an entry from `target` at `level` goes anywhere: the file, or the editor
+pub fn wants(target: &str, level: LogLevel) -> bool {
+    (is_wrapper_target(target) && !MIRROR_OFF.load(Ordering::Relaxed)) || target_allows(target, level)
+}
+
+/// Log an entry from `tracing` or `log`. `context` holds its other fields and, under
+/// `spans`, the spans it happened in; text lines append them, JSON lines include them.
+pub fn dispatch(target: &str, level: LogLevel, message: &str, mut context: Map<String, Value>) {
+    let wrapper = is_wrapper_target(target);
+    if wrapper {
+        mirror(level, &with_context(message, &context));
+    }
+    if !target_allows(target, level) {
+        return;
+    }
+    let source = if wrapper { Source::Wrapper } else { Source::Dependencies };
+    let at = Local::now();
+    if !JSON_FORMAT.load(Ordering::Relaxed) {
+        let line = match wrapper {
+            true => with_context(message, &context),
+            false => format!("[{target}] {}", with_context(message, &context)),
+        };
+        LOG_QUEUE.push(Record::Text(at, line));
+        return;
+    }
+    let message = message.strip_prefix("[roslyn_wrapper] ").unwrap_or(message);
+    context.insert("message".to_string(), json!(message));
+    context.insert("source".to_string(), json!(source.name()));
+    if !wrapper {
+        context.insert("target".to_string(), json!(target));
+    }
+    LOG_QUEUE.push(Record::Json(at, level, context));
+}
+
+/// `message key=value ... [span key=value ...]`: the entry's fields, then its spans
+fn with_context(message: &str, context: &Map<String, Value>) -> String {
+    let plain = |value: &Value| value.as_str().map_or_else(|| value.to_string(), String::from);
+    let mut text = message.to_string();
+    for (key, value) in context.iter().filter(|(key, _)| *key != "spans") {
+        let _ = write!(text, " {key}={}", plain(value));
+    }
+    for span in context.get("spans").and_then(Value::as_array).into_iter().flatten() {
+        let _ = write!(text, " [{}", span.get("span").map(plain).unwrap_or_default());
+        for (key, value) in span.as_object().into_iter().flatten().filter(|(key, _)| *key != "span") {
+            let _ = write!(text, " {key}={}", plain(value));
+        }
+        text.push(']');
+    }
+    text
+}
+
+fn mirror(level: LogLevel, message: &str) {
+    if !MIRROR_OFF.load(Ordering::Relaxed) {
+        if let Ok(mut mirror) = CLIENT_MIRROR.lock() {
+            mirror.push(level, message);
+        }
+    }
+}
+
+fn log_from(source: Source, level: LogLevel, message: &str) {
+    // Roslyn's own output and the protocol trace would only echo the editor's own traffic
+    if source == Source::Wrapper {
+        mirror(level, message);
+    }
+    let Some(format) = enabled(source, level) else {
+        return;
+    };
+    let at = Local::now();
+    match format {
+        LogFormat::Text => LOG_QUEUE.push(Record::Text(at, message.to_string())),
+        LogFormat::Json => {
+            let mut record = Map::new();
+            let message = message.strip_prefix("[roslyn_wrapper] ").unwrap_or(message);
+            record.insert("message".to_string(), json!(message));
+            record.insert("source".to_string(), json!(source.name()));
+            LOG_QUEUE.push(Record::Json(at, level, record));
         }
     }
 }
 
+pub fn log_line(message: impl AsRef<str>) {
+    log(LogLevel::Info, message.as_ref());
+}
+
 pub fn info(message: impl AsRef<str>) {
     log_line(message);
 }
 
 pub fn debug(message: impl AsRef<str>) {
-    if should_log(LogLevel::Debug) {
-        if let Ok(mut sink) = LOG_SINK.lock() {
-            sink.write_str(message.as_ref());
+    log(LogLevel::Debug, message.as_ref());
+}
+
+pub fn warn(message: impl AsRef<str>) {
+    log(LogLevel::Warn, message.as_ref());
+}
+
+pub fn error(message: impl AsRef<str>) {
+    log(LogLevel::Error, message.as_ref());
+}
+
+/// Log a line of Roslyn's output (`label` is the stream) at the severity it reports.
+/// Returns that severity, which continuation lines such as stack frames inherit.
+pub fn roslyn_output(label: &str, line: &str, previous: Option<LogLevel>) -> LogLevel {
+    let level = classify_roslyn_line(line, previous);
+    log_from(Source::RoslynStderr, level, &format!("[roslyn][{label}] {line}"));
+    level
+}
+
+fn classify_roslyn_line(line: &str, previous: Option<LogLevel>) -> LogLevel {
+    let text = line.trim_start();
+    // Microsoft.Extensions.Logging console prefixes
+    let prefixed = [
+        ("crit:", LogLevel::Error),
+        ("fail:", LogLevel::Error),
+        ("warn:", LogLevel::Warn),
+        ("info:", LogLevel::Info),
+        ("dbug:", LogLevel::Debug),
+        ("trce:", LogLevel::Trace),
+    ];
+    if let Some((_, level)) = prefixed.iter().find(|(prefix, _)| text.starts_with(prefix)) {
+        return *level;
+    }
+    if text.contains("Unhandled") || text.contains("Exception") {
+        return LogLevel::Error;
+    }
+    // Indented lines continue the entry above: message bodies and stack frames
+    let continuation = line.starts_with(char::is_whitespace) || text.starts_with("at ") || text.starts_with("---");
+    match previous {
+        Some(level) if continuation => level,
+        _ if text.starts_with("error") || text.starts_with("Error") => LogLevel::Error,
+        _ if text.starts_with("warning") || text.starts_with("Warning") => LogLevel::Warn,
+        _ => LogLevel::Debug,
+    }
+}
+
+/// Log one LSP message passing through the wrapper (debug level, with its payload
+/// at trace level). `client` is the connection it came from or goes to; `request`
+/// is the method and latency of the request a Roslyn response answers.
+pub fn message(direction: Direction, msg: &Message, client: Option<u64>, request: Option<(&str, Duration)>) {
+    let Some(format) = enabled(Source::Protocol, LogLevel::Debug) else {
+        return;
+    };
+    let at = Local::now();
+    let trace = enabled(Source::Protocol, LogLevel::Trace).map(|_| LOG_CONFIG.lock().unwrap().trace.clone());
+    let mut record = message_record(direction, msg, client, request);
+    let level = match &trace {
+        Some(_) => LogLevel::Trace,
+        None => LogLevel::Debug,
+    };
+    let payload = trace.map(|options| payload(msg, &options));
+    match format {
+        LogFormat::Text => {
+            let mut line = message_text(direction, &record);
+            if let Some((payload, _)) = payload {
+                line = format!("{line} {payload}");
+            }
+            LOG_QUEUE.push(Record::Text(at, line));
+        }
+        LogFormat::Json => {
+            if let Some((payload, truncated)) = payload {
+                let value = match truncated {
+                    true => None,
+                    false => serde_json::from_str(&payload).ok(),
+                };
+                record.insert("payload".to_string(), value.unwrap_or(Value::String(payload)));
+                if truncated {
+                    record.insert("payload_truncated".to_string(), json!(true));
+                }
+            }
+            LOG_QUEUE.push(Record::Json(at, level, record));
         }
     }
 }
 
-pub fn error(message: impl AsRef<str>) {
-    if should_log(LogLevel::Error) {
-        if let Ok(mut sink) = LOG_SINK.lock() {
-            sink.write_str(message.as_ref());
+/// The message body for the trace log, with document text redacted and cut to the
+/// size limit; the flag tells whether it was cut
+fn payload(msg: &Message, options: &TraceOptions) -> (String, bool) {
+    let body = match msg.method() {
+        Some(method) if options.redact_document_text && DOCUMENT_TEXT_METHODS.contains(&method) => {
+            let mut value = msg.to_value();
+            redact_document_text(&mut value);
+            value.to_string()
+        }
+        _ => String::from_utf8_lossy(msg.body()).into_owned(),
+    };
+    truncate(body, options.max_bytes)
+}
+
+fn redact_document_text(msg: &mut Value) {
+    let Some(params) = msg.get_mut("params") else {
+        return;
+    };
+    let redact = |text: &mut Value| {
+        if let Some(chars) = text.as_str().map(|t| t.chars().count()) {
+            *text = json!(format!("<redacted: {chars} chars>"));
+        }
+    };
+    // didOpen: textDocument.text, didSave: text, didChange: contentChanges[].text
+    if let Some(text) = params.pointer_mut("/textDocument/text") {
+        redact(text);
+    }
+    if let Some(text) = params.get_mut("text") {
+        redact(text);
+    }
+    if let Some(changes) = params.get_mut("contentChanges").and_then(Value::as_array_mut) {
+        for text in changes.iter_mut().filter_map(|change| change.get_mut("text")) {
+            redact(text);
+        }
+    }
+}
+
+fn truncate(mut body: String, max_bytes: Option<usize>) -> (String, bool) {
+    let Some(max_bytes) = max_bytes.filter(|max| body.len() > *max) else {
+        return (body, false);
+    };
+    let mut end = max_bytes;
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    let omitted = body.len() - end;
+    body.truncate(end);
+    let _ = write!(body, "… ({omitted} more bytes)");
+    (body, true)
+}
+
+fn message_record(direction: Direction, msg: &Message, client: Option<u64>, request: Option<(&str, Duration)>) -> Map<String, Value> {
+    let kind = match (msg.method(), msg.id()) {
+        (Some(_), Some(_)) => "request",
+        (Some(_), None) => "notification",
+        (None, _) => "response",
+    };
+    let mut record = Map::new();
+    record.insert("direction".to_string(), json!(direction.name()));
+    record.insert("kind".to_string(), json!(kind));
+    if let Some(method) = msg.method().or(request.map(|(method, _)| method)) {
+        record.insert("method".to_string(), json!(method));
+    }
+    if let Some(id) = msg.id() {
+        record.insert("id".to_string(), id.clone());
+    }
+    record.insert("bytes".to_string(), json!(msg.body().len()));
+    if let Some((_, latency)) = request {
+        record.insert("latency_ms".to_string(), json!(latency.as_millis() as u64));
+    }
+    if let Some(client) = client {
+        record.insert("client".to_string(), json!(client));
+    }
+    record
+}
+
+/// `<== FROM ROSLYN textDocument/hover #3 (response, 245 bytes, 120ms)`
+fn message_text(direction: Direction, record: &Map<String, Value>) -> String {
+    let mut line = format!("[roslyn_wrapper] {}", direction.arrow());
+    if let Some(method) = record.get("method").and_then(Value::as_str) {
+        let _ = write!(line, " {method}");
+    }
+    if let Some(id) = record.get("id") {
+        let _ = write!(line, " #{id}");
+    }
+    let _ = write!(line, " ({}, {} bytes", record["kind"].as_str().unwrap_or_default(), record["bytes"]);
+    if let Some(latency) = record.get("latency_ms") {
+        let _ = write!(line, ", {latency}ms");
+    }
+    if let Some(client) = record.get("client") {
+        let _ = write!(line, ", client {client}");
+    }
+    line.push(')');
+    line
+}
+
+/// What the writer thread is asked to do
+enum Record {
+    /// An entry, possibly spanning lines, with when it was logged
+    Text(DateTime<Local>, String),
+    Json(DateTime<Local>, LogLevel, Map<String, Value>),
+    /// Wakes the writer for a control sent while nothing else was queued
+    Wake,
+    /// Flush the file, then answer
+    Flush(std::sync::mpsc::Sender<()>),
+}
+
+/// Reconfiguration of the writer thread; never dropped, never waited on. Each one
+/// carries how many records were queued before it and is applied after those.
+enum Control {
+    Reopen(PathBuf),
+    Rotation(RotationOptions),
+}
+
+/// The sending side of the writer thread's bounded queue, and of its unbounded controls
+struct LogQueue {
+    sender: SyncSender<Record>,
+    controls: Sender<(u64, Control)>,
+    // Records queued so far, which place each control among them
+    queued: AtomicU64,
+    dropped: Arc<AtomicU64>,
+}
+
+impl LogQueue {
+    fn new(capacity: usize) -> (Self, Receiver<Record>, Receiver<(u64, Control)>) {
+        let (sender, records) = sync_channel(capacity);
+        let (controls, control_receiver) = channel();
+        let queue = Self {
+            sender,
+            controls,
+            queued: AtomicU64::new(0),
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        (queue, records, control_receiver)
+    }
+
+    /// Queue a reconfiguration, applied once the records queued before it are written.
+    /// When the wake-up doesn't fit, the queue is full and the writer is busy anyway
+    fn control(&self, control: Control) {
+        let _ = self.controls.send((self.queued.load(Ordering::SeqCst), control));
+        if self.sender.try_send(Record::Wake).is_ok() {
+            self.queued.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Queue an entry without waiting; a full queue drops it and counts the loss
+    fn push(&self, record: Record) {
+        match self.sender.try_send(record) {
+            Ok(()) => {
+                self.queued.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+
+    /// Queue a flush, waiting for room so it isn't lost
+    fn push_wait(&self, record: Record) -> bool {
+        let sent = self.sender.send(record).is_ok();
+        if sent {
+            self.queued.fetch_add(1, Ordering::SeqCst);
+        }
+        sent
+    }
+}
+
+/// The writer thread: write whatever is queued, then flush once for the batch
+fn run_writer(mut sink: LogSink, records: Receiver<Record>, controls: Receiver<(u64, Control)>, dropped: Arc<AtomicU64>) {
+    let mut written = 0;
+    let mut pending = VecDeque::new();
+    while let Ok(first) = records.recv() {
+        let mut flushed = Vec::new();
+        for record in std::iter::once(first).chain(records.try_iter().take(MAX_BATCH - 1)) {
+            apply_controls(&mut sink, &controls, &mut pending, written);
+            written += 1;
+            match record {
+                Record::Text(at, message) => sink.write_str(at, &message),
+                Record::Json(at, level, fields) => sink.write_json(at, level, fields),
+                Record::Wake => {}
+                Record::Flush(done) => flushed.push(done),
+            }
+        }
+        apply_controls(&mut sink, &controls, &mut pending, written);
+        let lost = dropped.swap(0, Ordering::Relaxed);
+        if lost > 0 {
+            let note = format!("[roslyn_wrapper] Dropped {lost} log entries: the log writer fell behind");
+            sink.write_str(Local::now(), &note);
+        }
+        sink.flush();
+        for done in flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Apply the controls that were sent after no more than `written` records
+fn apply_controls(
+    sink: &mut LogSink,
+    controls: &Receiver<(u64, Control)>,
+    pending: &mut VecDeque<(u64, Control)>,
+    written: u64,
+) {
+    pending.extend(controls.try_iter());
+    while pending.front().is_some_and(|(after, _)| *after <= written) {
+        match pending.pop_front() {
+            Some((_, Control::Reopen(path))) => sink.reopen(path),
+            Some((_, Control::Rotation(rotation))) => sink.rotation = rotation,
+            None => {}
         }
     }
 }
 
 struct LogSink {
     file: Option<File>,
+    path: PathBuf,
+    rotation: RotationOptions,
+    // Size of the current file, and when it was started
+    size: u64,
+    started: SystemTime,
 }
 
 impl LogSink {
     fn new(path: PathBuf) -> Self {
-        let mut file = initialize_file(&path);
-        if let Some(file_handle) = file.as_mut() {
-            let timestamp = Local::now().to_rfc3339_opts(SecondsFormat::Millis, true);
-            let _ = writeln!(
-                file_handle,
-                "[{}] [roslyn_wrapper] Logger initialized (path: {})",
-                timestamp,
-                path.display()
-            );
-            let _ = file_handle.flush();
-        }
-        Self { file }
+        let mut sink = Self {
+            file: None,
+            path: PathBuf::new(),
+            rotation: RotationOptions::default(),
+            size: 0,
+            started: SystemTime::now(),
+        };
+        sink.reopen(path);
+        let initialized = format!("[roslyn_wrapper] Logger initialized (path: {})", sink.path.display());
+        sink.write_str(Local::now(), &initialized);
+        sink.flush();
+        sink
     }
 
     fn reopen(&mut self, path: PathBuf) {
         self.file = initialize_file(&path);
+        let metadata = self.file.as_ref().and_then(|f| f.metadata().ok());
+        self.size = metadata.as_ref().map_or(0, |m| m.len());
+        // An existing file's age counts from when it was created, where the platform knows
+        self.started = metadata.and_then(|m| m.created().ok()).unwrap_or_else(SystemTime::now);
+        self.path = path;
+    }
+
+    /// One JSON object per line, carrying the timestamp, level and session
+    fn write_json(&mut self, at: DateTime<Local>, level: LogLevel, fields: Map<String, Value>) {
+        self.write_lines(&format!("{}\n", json_line(at, level, fields)));
     }
 
-    fn write_str(&mut self, message: &str) {
+    fn write_str(&mut self, at: DateTime<Local>, message: &str) {
+        let timestamp = at.to_rfc3339_opts(SecondsFormat::Millis, true);
+        let mut lines = String::new();
+        for line in message.lines() {
+            let _ = writeln!(lines, "[{timestamp}] {line}");
+        }
+        self.write_lines(&lines);
+    }
+
+    fn write_lines(&mut self, lines: &str) {
+        if self.due_for_rotation() {
+            self.rotate();
+        }
+        if let Some(file) = self.file.as_mut() {
+            let _ = file.write_all(lines.as_bytes());
+            self.size += lines.len() as u64;
+        }
+    }
+
+    fn flush(&mut self) {
         if let Some(file) = self.file.as_mut() {
-            let timestamp = Local::now().to_rfc3339_opts(SecondsFormat::Millis, true);
-            for line in message.lines() {
-                let _ = writeln!(file, "[{timestamp}] {line}");
-            }
             let _ = file.flush();
         }
     }
+
+    fn due_for_rotation(&self) -> bool {
+        let too_big = self.rotation.max_bytes.is_some_and(|max| self.size >= max);
+        let too_old = self
+            .rotation
+            .max_age
+            .is_some_and(|max| self.size > 0 && self.started.elapsed().is_ok_and(|age| age >= max));
+        too_big || too_old
+    }
+
+    /// Move the current file to `.1`, shifting older ones up and dropping the oldest
+    fn rotate(&mut self) {
+        self.file = None;
+        let rotated = |n: usize, suffix: &str| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{n}{suffix}"));
+            PathBuf::from(name)
+        };
+        for n in (1..=self.rotation.max_files).rev() {
+            for suffix in ["", ".gz"] {
+                let from = rotated(n, suffix);
+                if !from.exists() {
+                    continue;
+                }
+                let _ = match n == self.rotation.max_files {
+                    true => std::fs::remove_file(&from),
+                    false => std::fs::rename(&from, rotated(n + 1, suffix)),
+                };
+            }
+        }
+        if self.rotation.max_files == 0 {
+            let _ = std::fs::remove_file(&self.path);
+        } else if std::fs::rename(&self.path, rotated(1, "")).is_ok() && self.rotation.compress {
+            if compress(&rotated(1, ""), &rotated(1, ".gz")).is_ok() {
+                let _ = std::fs::remove_file(rotated(1, ""));
+            } else {
+                let _ = std::fs::remove_file(rotated(1, ".gz"));
+            }
+        }
+        self.reopen(self.path.clone());
+    }
+}
+
+fn compress(from: &Path, to: &Path) -> std::io::Result<()> {
+    let mut input = File::open(from)?;
+    let mut encoder = GzEncoder::new(File::create(to)?, Compression::default());
+    std::io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.flush()
+}
+
+fn json_line(at: DateTime<Local>, level: LogLevel, fields: Map<String, Value>) -> String {
+    let mut record = Map::new();
+    record.insert("timestamp".to_string(), json!(at.to_rfc3339_opts(SecondsFormat::Millis, true)));
+    record.insert("level".to_string(), json!(level.name()));
+    record.insert("session".to_string(), json!(*SESSION_ID));
+    record.extend(fields);
+    Value::Object(record).to_string()
 }
 
+/// `roslyn_wrapper.log` under the platform's state directory (`~/.local/state/roslyn-wrapper/logs`
+/// on Linux), or its cache directory where there is none
 fn default_log_file_path() -> PathBuf {
-    std::env::current_dir()
-        .unwrap_or_else(|_| std::env::temp_dir())
+    ProjectDirs::from("com", "github", "roslyn-wrapper")
+        .map(|dirs| dirs.state_dir().unwrap_or(dirs.cache_dir()).join("logs"))
+        .unwrap_or_else(std::env::temp_dir)
         .join("roslyn_wrapper.log")
 }
 
@@ -172,6 +1054,179 @@ mod tests {
         debug("[roslyn_wrapper] test debug");
     }
 
+    #[test]
+    fn messages_are_logged_with_their_metadata() {
+        let response = Message::from(json!({ "jsonrpc": "2.0", "id": 3, "result": null }));
+        let request = Some(("textDocument/hover", Duration::from_millis(120)));
+        let record = message_record(Direction::FromRoslyn, &response, Some(2), request);
+        assert_eq!(
+            message_text(Direction::FromRoslyn, &record),
+            format!(
+                "[roslyn_wrapper] <== FROM ROSLYN textDocument/hover #3 (response, {} bytes, 120ms, client 2)",
+                response.body().len()
+            )
+        );
+
+        let line: Value = serde_json::from_str(&json_line(Local::now(), LogLevel::Debug, record)).unwrap();
+        assert_eq!(line["session"], *SESSION_ID);
+        assert_eq!(line["direction"], "from_roslyn");
+        assert_eq!(line["method"], "textDocument/hover");
+        assert_eq!(line["latency_ms"], 120);
+        assert_eq!(line["bytes"], response.body().len());
+    }
+
+    #[test]
+    fn trace_payloads_hide_document_text_and_respect_the_size_limit() {
+        let change = Message::from(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": "file:///a.cs", "version": 2 },
+                "contentChanges": [{ "text": "class Secret {}" }]
+            }
+        }));
+        let options = TraceOptions { max_bytes: None, redact_document_text: true };
+        let (payload, truncated) = payload(&change, &options);
+        assert!(!truncated);
+        assert!(payload.contains("<redacted: 15 chars>") && !payload.contains("Secret"));
+
+        let shown = TraceOptions { max_bytes: Some(20), redact_document_text: false };
+        let (payload, truncated) = super::payload(&change, &shown);
+        assert!(truncated);
+        assert_eq!(payload, format!("{}… ({} more bytes)", &change_body(&change)[..20], change.body().len() - 20));
+    }
+
+    fn change_body(msg: &Message) -> String {
+        String::from_utf8_lossy(msg.body()).into_owned()
+    }
+
+    #[test]
+    fn rotated_logs_are_shifted_compressed_and_pruned() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("roslyn_wrapper.log");
+        let mut sink = LogSink::new(path.clone());
+        sink.rotation = RotationOptions { max_bytes: Some(100), max_age: None, max_files: 2, compress: true };
+        for n in 0..4 {
+            sink.write_str(Local::now(), &format!("[roslyn_wrapper] line {n} {}", "x".repeat(100)));
+        }
+
+        let kept = |name: &str| tmp.path().join(name).exists();
+        assert!(kept("roslyn_wrapper.log") && kept("roslyn_wrapper.log.1.gz") && kept("roslyn_wrapper.log.2.gz"));
+        assert!(!kept("roslyn_wrapper.log.1") && !kept("roslyn_wrapper.log.3.gz"));
+        let mut newest = String::new();
+        let gz = File::open(tmp.path().join("roslyn_wrapper.log.1.gz")).unwrap();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(gz), &mut newest).unwrap();
+        assert!(newest.contains("line 2"));
+        assert!(std::fs::read_to_string(&path).unwrap().contains("line 3"));
+    }
+
+    #[test]
+    fn client_entries_are_held_until_the_connection_is_up() {
+        let (sender, mut entries) = mpsc::unbounded_channel();
+        let mut mirror = ClientMirror { sender: Some(sender), ..ClientMirror::default() };
+        mirror.push(LogLevel::Debug, "[roslyn_wrapper] Downloading Roslyn");
+        mirror.push(LogLevel::Error, "[roslyn_wrapper] Download failed");
+        mirror.level = Some(LogLevel::Info);
+        mirror.push(LogLevel::Info, "[roslyn_wrapper] Captured workspace rootUri");
+        assert!(entries.try_recv().is_err());
+
+        mirror.connect();
+        mirror.push(LogLevel::Debug, "[roslyn_wrapper] Not for the editor");
+        mirror.push(LogLevel::Info, "[roslyn_wrapper] Opening solution");
+        let received: Vec<_> = std::iter::from_fn(|| entries.try_recv().ok()).collect();
+        assert_eq!(
+            received,
+            vec![
+                (LSP_MESSAGE_TYPE_ERROR, "[roslyn_wrapper] Download failed".to_string()),
+                (LSP_MESSAGE_TYPE_INFO, "[roslyn_wrapper] Captured workspace rootUri".to_string()),
+                (LSP_MESSAGE_TYPE_INFO, "[roslyn_wrapper] Opening solution".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn dependency_targets_have_their_own_levels_and_entries_show_their_spans() {
+        let levels = json!({ "protocol": "trace", "hyper": "debug", "hyper::proto": "off", "h2": 1 });
+        let (overrides, targets, ignored) = source_levels(levels.as_object().unwrap());
+        assert_eq!(overrides, HashMap::from([(Source::Protocol, LogLevel::Trace)]));
+        assert_eq!(ignored, vec!["h2"]);
+        assert_eq!(named_level(&targets, "hyper"), Some(LogLevel::Debug));
+        assert_eq!(named_level(&targets, "hyper::client::pool"), Some(LogLevel::Debug));
+        assert_eq!(named_level(&targets, "hyper::proto::h1"), Some(LogLevel::Off));
+        assert_eq!(named_level(&targets, "hyper_util"), None);
+
+        let context = json!({
+            "attempt": 2,
+            "spans": [{ "span": "request", "method": "textDocument/hover", "id": 3 }]
+        });
+        assert_eq!(
+            with_context("[roslyn_wrapper] Retrying", context.as_object().unwrap()),
+            "[roslyn_wrapper] Retrying attempt=2 [request id=3 method=textDocument/hover]"
+        );
+    }
+
+    #[test]
+    fn roslyn_output_is_classified_by_severity() {
+        assert_eq!(classify_roslyn_line("fail: Microsoft.CodeAnalysis.LanguageServer[0]", None), LogLevel::Error);
+        assert_eq!(classify_roslyn_line("      Failed to load project", Some(LogLevel::Error)), LogLevel::Error);
+        assert_eq!(classify_roslyn_line("warn: Program[0]", None), LogLevel::Warn);
+        assert_eq!(classify_roslyn_line("info: Program[0]", None), LogLevel::Info);
+        assert_eq!(classify_roslyn_line("      Language server initialized", Some(LogLevel::Info)), LogLevel::Info);
+        assert_eq!(
+            classify_roslyn_line("Unhandled exception. System.InvalidOperationException: boom", None),
+            LogLevel::Error
+        );
+        assert_eq!(classify_roslyn_line("   at Program.Main(String[] args)", Some(LogLevel::Error)), LogLevel::Error);
+        assert_eq!(classify_roslyn_line("error: A compatible .NET SDK was not found.", None), LogLevel::Error);
+        assert_eq!(classify_roslyn_line("Starting up", None), LogLevel::Debug);
+    }
+
+    #[test]
+    fn a_full_queue_drops_entries_and_the_writer_reports_them() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("roslyn_wrapper.log");
+        let (queue, records, controls) = LogQueue::new(2);
+        for n in 0..5 {
+            queue.push(Record::Text(Local::now(), format!("[roslyn_wrapper] entry {n}")));
+        }
+        assert_eq!(queue.dropped.load(Ordering::Relaxed), 3);
+
+        let (sink, dropped) = (LogSink::new(path.clone()), queue.dropped.clone());
+        let writer = std::thread::spawn(move || run_writer(sink, records, controls, dropped));
+        let (done, flushed) = std::sync::mpsc::channel();
+        assert!(queue.push_wait(Record::Flush(done)));
+        flushed.recv_timeout(FLUSH_TIMEOUT).unwrap();
+        drop(queue);
+        writer.join().unwrap();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(log.contains("entry 1") && !log.contains("entry 2"));
+        assert!(log.contains("Dropped 3 log entries"));
+    }
+
+    #[test]
+    fn reconfiguring_a_full_queue_neither_blocks_nor_is_lost() {
+        let tmp = tempdir().unwrap();
+        let (first, second) = (tmp.path().join("first.log"), tmp.path().join("second.log"));
+        let (queue, records, controls) = LogQueue::new(1);
+        queue.push(Record::Text(Local::now(), "[roslyn_wrapper] before".to_string()));
+        // The queue is full and nothing drains it yet
+        queue.control(Control::Reopen(second.clone()));
+
+        let (sink, dropped) = (LogSink::new(first.clone()), queue.dropped.clone());
+        let writer = std::thread::spawn(move || run_writer(sink, records, controls, dropped));
+        queue.push_wait(Record::Text(Local::now(), "[roslyn_wrapper] after".to_string()));
+        let (done, flushed) = std::sync::mpsc::channel();
+        assert!(queue.push_wait(Record::Flush(done)));
+        flushed.recv_timeout(FLUSH_TIMEOUT).unwrap();
+        drop(queue);
+        writer.join().unwrap();
+
+        let (first, second) = (std::fs::read_to_string(&first).unwrap(), std::fs::read_to_string(&second).unwrap());
+        assert!(first.contains("before") && !first.contains("after"));
+        assert!(second.contains("after") && !second.contains("before"));
+    }
+
     #[test]
     fn configure_directory_sets_default_filename() {
         let tmp = tempdir().unwrap();
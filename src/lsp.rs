@@ -0,0 +1,498 @@
+// LSP base protocol framing: `Content-Length` delimited JSON-RPC messages.
+//
+// The reader is deliberately forgiving. Stray output before a header block is
+// skipped, a malformed message is reported as `Incoming::Malformed` (so the caller
+// can answer with a JSON-RPC ParseError) and reading continues with the next frame.
+// Only I/O errors and a stream that ends mid-message are fatal.
+//
+// Bodies are kept as the bytes that were read. Only `method` and `id` are decoded
+// up front, so a message the wrapper does not rewrite is forwarded without ever
+// being turned into a `serde_json::Value` and serialized again.
+use std::borrow::Cow;
+use std::io;
+use std::ops::Range;
+
+use bytes::Bytes;
+use serde::{Deserialize, Deserializer};
+use serde_json::value::RawValue;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::logger;
+
+/// Largest body accepted; anything bigger is treated as a corrupted header
+pub const MAX_CONTENT_LENGTH: usize = 64 * 1024 * 1024;
+
+// Longest header line kept in memory; longer lines are stray output
+const MAX_HEADER_LINE: u64 = 8 * 1024;
+
+/// JSON-RPC error code for invalid JSON
+pub const JSONRPC_PARSE_ERROR: i64 = -32700;
+
+// LSP Message Type Constants (for window/showMessage and window/logMessage)
+pub const LSP_MESSAGE_TYPE_ERROR: i64 = 1;
+pub const LSP_MESSAGE_TYPE_WARNING: i64 = 2;
+pub const LSP_MESSAGE_TYPE_INFO: i64 = 3;
+pub const LSP_MESSAGE_TYPE_LOG: i64 = 4;
+
+/// One frame read from an LSP stream
+#[derive(Debug, PartialEq)]
+pub enum Incoming {
+    Message(Message),
+    /// A frame that could not be turned into a JSON-RPC message; the stream stays usable
+    Malformed(String),
+}
+
+/// Read the next message from an LSP stream. Returns `Ok(None)` on a clean EOF.
+pub async fn read_lsp_message<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<Option<Incoming>> {
+    let mut content_length: Option<usize> = None;
+    let mut problem: Option<String> = None;
+    let mut in_headers = false;
+    let mut line = Vec::new();
+
+    // Read headers until empty line
+    loop {
+        line.clear();
+        let n = (&mut *reader)
+            .take(MAX_HEADER_LINE)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if n == 0 {
+            if in_headers {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Stream ended inside LSP message headers",
+                ));
+            }
+            return Ok(None); // EOF
+        }
+
+        let text = String::from_utf8_lossy(&line);
+        let trimmed = text.trim();
+        if trimmed.is_empty() {
+            if in_headers {
+                break;
+            }
+            continue;
+        }
+
+        // Resync on a header glued to stray output, e.g. `garbage}Content-Length: 12`
+        let header = match find_ignore_ascii_case(trimmed, "content-length:") {
+            Some(pos) if pos > 0 => {
+                logger::debug(format!(
+                    "[roslyn_wrapper] Skipping stray output before header: {}",
+                    &trimmed[..pos]
+                ));
+                &trimmed[pos..]
+            }
+            _ => trimmed,
+        };
+
+        let Some((name, value)) = header.split_once(':') else {
+            if in_headers {
+                problem.get_or_insert_with(|| format!("Malformed header line: {header}"));
+            } else {
+                logger::debug(format!("[roslyn_wrapper] Skipping stray output: {header}"));
+            }
+            continue;
+        };
+        let value = value.trim();
+
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            in_headers = true;
+            match value.parse::<usize>() {
+                Ok(len) => content_length = Some(len),
+                Err(_) => {
+                    problem.get_or_insert_with(|| format!("Invalid Content-Length: {value}"));
+                }
+            }
+        } else if name.trim().eq_ignore_ascii_case("content-type") {
+            in_headers = true;
+            if let Some(charset) = content_type_charset(value) {
+                if !matches!(charset.as_str(), "utf-8" | "utf8") {
+                    problem.get_or_insert_with(|| format!("Unsupported charset: {charset}"));
+                }
+            }
+        } else if !in_headers {
+            logger::debug(format!("[roslyn_wrapper] Skipping stray output: {header}"));
+        }
+        // Other headers inside a header block are allowed and ignored
+    }
+
+    let content_length = match content_length {
+        None => {
+            return Ok(Some(Incoming::Malformed(
+                problem.unwrap_or_else(|| "Missing Content-Length header".to_string()),
+            )))
+        }
+        Some(0) => return Ok(Some(Incoming::Malformed("Empty message body".to_string()))),
+        // Don't try to read the body; the next header resyncs the stream
+        Some(len) if len > MAX_CONTENT_LENGTH => {
+            return Ok(Some(Incoming::Malformed(format!(
+                "Content-Length {len} exceeds the {MAX_CONTENT_LENGTH} byte limit"
+            ))))
+        }
+        Some(len) => len,
+    };
+
+    // Read body; read_exact keeps going across partial reads
+    let mut buf = vec![0; content_length];
+    reader.read_exact(&mut buf).await?;
+
+    if let Some(problem) = problem {
+        return Ok(Some(Incoming::Malformed(problem)));
+    }
+
+    match Message::parse(Bytes::from(buf)) {
+        Ok(msg) => Ok(Some(Incoming::Message(msg))),
+        Err(reason) => Ok(Some(Incoming::Malformed(reason))),
+    }
+}
+
+/// Send an LSP message to a writer, exactly as it was read (or last rewritten)
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    msg: &Message,
+) -> io::Result<()> {
+    let header = format!("Content-Length: {}\r\n\r\n", msg.body().len());
+
+    writer.write_all(header.as_bytes()).await?;
+    writer.write_all(msg.body()).await?;
+    writer.flush().await?;
+
+    Ok(())
+}
+
+/// A JSON-RPC message with only its routing fields decoded.
+///
+/// The body stays as received; `to_value` parses the rest on demand for the
+/// few messages the wrapper rewrites. Cloning shares the body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    body: Bytes,
+    method: Option<String>,
+    id: Option<Value>,
+    // Where the `id` value sits in `body`, so it can be replaced in place
+    id_span: Option<Range<usize>>,
+}
+
+/// The fields the router looks at; everything else is skipped without allocating
+#[derive(Deserialize)]
+struct Envelope<'a> {
+    #[serde(default, borrow)]
+    method: Option<Cow<'a, str>>,
+    // `"id": null` is still an id, so don't let `Option` swallow it
+    #[serde(default, borrow, deserialize_with = "present")]
+    id: Option<&'a RawValue>,
+}
+
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<&'de RawValue>, D::Error> {
+    <&RawValue>::deserialize(deserializer).map(Some)
+}
+
+impl Message {
+    /// Validate a frame body and decode its `method` and `id`
+    pub fn parse(body: Bytes) -> Result<Self, String> {
+        if body.iter().find(|b| !b.is_ascii_whitespace()) != Some(&b'{') {
+            return Err("Message body is not a JSON object".to_string());
+        }
+        let envelope: Envelope =
+            serde_json::from_slice(&body).map_err(|e| format!("Invalid JSON: {e}"))?;
+
+        let method = envelope.method.map(Cow::into_owned);
+        let (id, id_span) = match envelope.id {
+            Some(raw) => {
+                let id = serde_json::from_str(raw.get()).map_err(|e| format!("Invalid JSON: {e}"))?;
+                // The raw value borrows from `body`, so its offset locates it there
+                let start = raw.get().as_ptr() as usize - body.as_ptr() as usize;
+                (Some(id), Some(start..start + raw.get().len()))
+            }
+            None => (None, None),
+        };
+        Ok(Self { body, method, id, id_span })
+    }
+
+    pub fn method(&self) -> Option<&str> {
+        self.method.as_deref()
+    }
+
+    pub fn id(&self) -> Option<&Value> {
+        self.id.as_ref()
+    }
+
+    /// The serialized message as it goes on the wire
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Whether a response has no `result` or a `null` one, checked without a full parse
+    pub fn result_is_null(&self) -> bool {
+        #[derive(Deserialize)]
+        struct Probe<'a> {
+            #[serde(default, borrow)]
+            result: Option<&'a RawValue>,
+        }
+        serde_json::from_slice::<Probe>(&self.body).is_ok_and(|probe| probe.result.is_none())
+    }
+
+    /// Whether `text` occurs anywhere in the body, to skip parsing messages a rule can't apply to
+    pub fn mentions(&self, text: &str) -> bool {
+        let text = text.as_bytes();
+        !text.is_empty() && self.body.windows(text.len()).any(|window| window == text)
+    }
+
+    /// Fully parse the message, for rules that need more than `method` and `id`
+    pub fn to_value(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or(Value::Null)
+    }
+
+    /// The same message with a different `id`, leaving every other byte untouched
+    pub fn with_id(self, id: &Value) -> Self {
+        let Some(span) = self.id_span.clone() else {
+            return self;
+        };
+        let id_text = id.to_string();
+        let mut body = Vec::with_capacity(self.body.len() - span.len() + id_text.len());
+        body.extend_from_slice(&self.body[..span.start]);
+        body.extend_from_slice(id_text.as_bytes());
+        body.extend_from_slice(&self.body[span.end..]);
+        Self {
+            body: Bytes::from(body),
+            method: self.method,
+            id: Some(id.clone()),
+            id_span: Some(span.start..span.start + id_text.len()),
+        }
+    }
+}
+
+impl From<Value> for Message {
+    fn from(value: Value) -> Self {
+        let body = Bytes::from(value.to_string());
+        // Wrapper-built messages are small, so reading the envelope back is cheap
+        Message::parse(body.clone()).unwrap_or(Self {
+            body,
+            method: None,
+            id: None,
+            id_span: None,
+        })
+    }
+}
+
+/// JSON-RPC error response for a message that could not be parsed
+pub fn parse_error(detail: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": null,
+        "error": {
+            "code": JSONRPC_PARSE_ERROR,
+            "message": format!("Parse error: {detail}")
+        }
+    })
+}
+
+/// `$/progress` notification for a work-done progress token
+pub fn progress(token: &str, value: Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "$/progress",
+        "params": { "token": token, "value": value }
+    })
+}
+
+fn find_ignore_ascii_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|w| w.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// `charset` parameter of a Content-Type value, lowercased and unquoted
+fn content_type_charset(value: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let (key, val) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| val.trim().trim_matches('"').to_lowercase())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, ReadBuf};
+    use tokio_test::block_on;
+
+    fn read_all(bytes: &[u8]) -> (Vec<Incoming>, io::Result<()>) {
+        let mut reader = bytes;
+        let mut out = Vec::new();
+        loop {
+            match block_on(read_lsp_message(&mut reader)) {
+                Ok(Some(incoming)) => out.push(incoming),
+                Ok(None) => return (out, Ok(())),
+                Err(e) => return (out, Err(e)),
+            }
+        }
+    }
+
+    fn encode(messages: &[Value]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for msg in messages {
+            block_on(write_message(&mut bytes, &Message::from(msg.clone()))).unwrap();
+        }
+        bytes
+    }
+
+    fn message(body: &str) -> Incoming {
+        Incoming::Message(Message::parse(Bytes::copy_from_slice(body.as_bytes())).unwrap())
+    }
+
+    fn frame(body: &str) -> Vec<u8> {
+        format!("Content-Length: {}\r\n\r\n{body}", body.len()).into_bytes()
+    }
+
+    /// Reader that hands out at most one byte per read, like a slow pipe
+    struct Trickle<'a>(&'a [u8]);
+
+    impl AsyncRead for Trickle<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            if let Some((first, rest)) = self.0.split_first() {
+                if buf.remaining() > 0 {
+                    buf.put_slice(&[*first]);
+                    self.0 = rest;
+                }
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn headers_are_case_insensitive_and_extra_headers_ignored() {
+        let body = r#"{"jsonrpc":"2.0","method":"initialized"}"#;
+        let bytes = format!(
+            "content-type: application/vscode-jsonrpc; charset=\"UTF-8\"\r\nX-Custom: 1\r\nCONTENT-LENGTH: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let (messages, result) = read_all(bytes.as_bytes());
+        assert!(result.is_ok());
+        assert_eq!(messages, [message(body)]);
+    }
+
+    #[test]
+    fn malformed_frames_do_not_end_the_stream() {
+        let good = r#"{"jsonrpc":"2.0","id":1,"result":null}"#;
+        let mut bytes = Vec::new();
+        bytes.extend(frame("{not json"));
+        bytes.extend(b"Content-Length: 0\r\n\r\n");
+        bytes.extend(b"Content-Type: text/plain; charset=latin1\r\nContent-Length: 2\r\n\r\n{}");
+        bytes.extend(b"Content-Length: 99999999999\r\n\r\n");
+        bytes.extend(frame("[1,2]"));
+        bytes.extend(frame(good));
+
+        let (messages, result) = read_all(&bytes);
+        assert!(result.is_ok());
+        assert_eq!(messages.len(), 6);
+        assert!(messages[..5].iter().all(|m| matches!(m, Incoming::Malformed(_))));
+        assert_eq!(messages[5], message(good));
+    }
+
+    #[test]
+    fn stray_output_is_skipped() {
+        let good = r#"{"jsonrpc":"2.0","method":"exit"}"#;
+        let mut bytes = b"Hello from an analyzer\r\n\r\nwarning}".to_vec();
+        bytes.extend(frame(good));
+        let (messages, result) = read_all(&bytes);
+        assert!(result.is_ok());
+        assert_eq!(messages, [message(good)]);
+    }
+
+    #[test]
+    fn truncated_body_is_an_error() {
+        let (_, result) = read_all(b"Content-Length: 10\r\n\r\n{}");
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn envelope_is_decoded_without_touching_the_body() {
+        let body = r#"{ "jsonrpc": "2.0", "result": {"id": 9, "method": "x"}, "id" : 41 }"#;
+        let Incoming::Message(msg) = message(body) else {
+            unreachable!()
+        };
+        assert_eq!(msg.method(), None);
+        assert_eq!(msg.id(), Some(&json!(41)));
+        assert_eq!(msg.body(), body.as_bytes());
+
+        let renamed = msg.with_id(&json!("c1-7"));
+        assert_eq!(
+            renamed.body(),
+            br#"{ "jsonrpc": "2.0", "result": {"id": 9, "method": "x"}, "id" : "c1-7" }"#
+        );
+        assert_eq!(renamed.with_id(&json!(41)).body(), body.as_bytes());
+    }
+
+    #[test]
+    fn null_id_is_kept() {
+        let Incoming::Message(msg) = message(r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700}}"#) else {
+            unreachable!()
+        };
+        assert_eq!(msg.id(), Some(&Value::Null));
+        assert_eq!(message(r#"{"method":"exit"}"#), Incoming::Message(Message::from(json!({ "method": "exit" }))));
+    }
+
+    fn json_message() -> impl Strategy<Value = Value> {
+        (
+            any::<i64>(),
+            "[a-zA-Z/$_]{1,40}",
+            prop::collection::hash_map("[a-z]{1,8}", ".{0,64}", 0..6),
+        )
+            .prop_map(|(id, method, params)| {
+                json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn random_bytes_never_panic_or_hang(bytes in prop::collection::vec(any::<u8>(), 0..4096)) {
+            // Every call either consumes input or stops, so this terminates
+            let _ = read_all(&bytes);
+        }
+
+        #[test]
+        fn framed_messages_round_trip(messages in prop::collection::vec(json_message(), 1..8)) {
+            let bytes = encode(&messages);
+
+            // Partial reads: the buffered reader only ever sees one byte at a time
+            let mut reader = tokio::io::BufReader::with_capacity(3, Trickle(&bytes));
+            for msg in &messages {
+                let read = block_on(read_lsp_message(&mut reader)).unwrap();
+                prop_assert_eq!(read, Some(Incoming::Message(Message::from(msg.clone()))));
+            }
+            prop_assert_eq!(block_on(read_lsp_message(&mut reader)).unwrap(), None);
+        }
+
+        #[test]
+        fn recovers_after_garbage(
+            garbage in prop::collection::vec("[a-zA-Z0-9 .{}\\[\\]]{0,64}", 0..5),
+            msg in json_message(),
+        ) {
+            let mut bytes = Vec::new();
+            for line in &garbage {
+                bytes.extend(line.as_bytes());
+                bytes.extend(b"\r\n");
+            }
+            bytes.extend(encode(std::slice::from_ref(&msg)));
+
+            let (messages, result) = read_all(&bytes);
+            prop_assert!(result.is_ok());
+            prop_assert_eq!(messages.last(), Some(&Incoming::Message(Message::from(msg))));
+        }
+    }
+}
@@ -1,99 +1,107 @@
-use std::collections::HashMap;
-use std::io::{self, BufRead, BufReader, Read, Write};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+use std::time::Duration;
 
-use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
 
+mod commands;
+mod config;
+mod custom;
+mod daemon;
 mod download;
+mod lifecycle;
+mod loading;
+mod log_bridge;
 mod logger;
+mod lsp;
 mod path_utils;
+mod proxy;
+mod recording;
+mod restore;
+mod timeouts;
+mod transport;
 
-// LSP Message Type Constants (for window/showMessage)
-const LSP_MESSAGE_TYPE_ERROR: i64 = 1;
-const LSP_MESSAGE_TYPE_WARNING: i64 = 2;
-const LSP_MESSAGE_TYPE_INFO: i64 = 3;
+use proxy::Proxy;
+use transport::{ListenAddr, Listener, LspReader, LspWriter, RoslynTransport};
 
-// Roslyn Message Type Constants (for window/_roslyn_showToast)
-const ROSLYN_MESSAGE_TYPE_ERROR: i64 = 3;
-const ROSLYN_MESSAGE_TYPE_WARNING: i64 = 1;
-const ROSLYN_MESSAGE_TYPE_INFO: i64 = 2;
-
-/// LSP Message Wrapper for Roslyn
-///
-/// This wrapper acts as a proxy between Zed and the Roslyn Language Server.
-/// Key responsibilities:
-/// 1. Start Roslyn subprocess
-/// 2. Forward LSP messages bidirectionally using async tasks
-/// 3. Inject `solution/open` notification after initialization
-/// 4. Handle edge cases and logging
+/// Remove a wrapper-specific `--name value` / `--name=value` option from the arguments.
 ///
-/// Parse LSP message header and body from a reader
-fn read_lsp_message<R: Read + BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
-    let mut content_length = 0;
-    let mut line = String::new();
-
-    // Read headers until empty line
-    loop {
-        line.clear();
-        let n = reader.read_line(&mut line)?;
-        if n == 0 {
-            return Ok(None); // EOF
-        }
-
-        let line_trimmed = line.trim();
-        if line_trimmed.is_empty() {
-            break;
-        }
-
-        if line_trimmed.starts_with("Content-Length:") {
-            let parts: Vec<&str> = line_trimmed.split(':').collect();
-            if let Some(len_str) = parts.get(1) {
-                content_length = len_str.trim().parse().unwrap_or(0);
-            }
-        }
-    }
-
-    if content_length == 0 {
+/// Wrapper options are stripped before the pass-through check so that they are
+/// never forwarded to Roslyn. An option given last without its value is an error.
+fn take_option(args: &mut Vec<String>, name: &str) -> io::Result<Option<String>> {
+    let prefix = format!("{name}=");
+    let Some(pos) = args.iter().skip(1).position(|a| a == name || a.starts_with(&prefix)) else {
         return Ok(None);
+    };
+    let pos = pos + 1;
+    let arg = args.remove(pos);
+    if let Some(value) = arg.strip_prefix(&prefix) {
+        Ok(Some(value.to_string()))
+    } else if pos < args.len() {
+        Ok(Some(args.remove(pos)))
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Missing value for {name}")))
     }
+}
 
-    // Read body
-    let mut buf = vec![0; content_length];
-    reader.read_exact(&mut buf)?;
-
-    let body = String::from_utf8_lossy(&buf);
-    match serde_json::from_str::<Value>(&body) {
-        Ok(value) => Ok(Some(value)),
-        Err(e) => {
-            logger::error(format!("[roslyn_wrapper] Failed to parse LSP message: {e}"));
-            Ok(None)
+/// Remove a wrapper-specific boolean `--name` flag from the arguments
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().skip(1).position(|a| a == name) {
+        Some(pos) => {
+            args.remove(pos + 1);
+            true
         }
+        None => false,
     }
 }
 
-/// Send an LSP message to a writer
-fn send_lsp_message<W: Write>(writer: &mut W, msg: &Value) -> io::Result<()> {
-    let json_str = msg.to_string();
-    let header = format!("Content-Length: {}\r\n\r\n", json_str.len());
-
-    writer.write_all(header.as_bytes())?;
-    writer.write_all(json_str.as_bytes())?;
-    writer.flush()?;
-
-    Ok(())
+/// Log every line from one of Roslyn's output streams, at the severity it reports, until it closes
+fn spawn_output_logger<R: AsyncBufRead + Send + Unpin + 'static>(
+    mut reader: R,
+    label: &'static str,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut line = String::new();
+        let mut previous = None;
+        loop {
+            line.clear();
+            match reader.read_line(&mut line).await {
+                Ok(0) => break,
+                Ok(_) => {
+                    let msg = line.trim_end();
+                    if !msg.is_empty() {
+                        previous = Some(logger::roslyn_output(label, msg, previous));
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    })
 }
 
-fn main() -> io::Result<()> {
+/// LSP Message Wrapper for Roslyn
+///
+/// This wrapper acts as a proxy between Zed and the Roslyn Language Server.
+/// Key responsibilities:
+/// 1. Start Roslyn subprocess
+/// 2. Route LSP messages between the client(s) and Roslyn through a single async router
+/// 3. Inject `solution/open` notification after initialization
+/// 4. Handle edge cases and logging
+/// 5. Shut Roslyn down with the session and exit non-zero if that wasn't clean
+fn main() -> io::Result<ExitCode> {
+    log_bridge::install();
     // Use tokio runtime to run async code
     let rt = tokio::runtime::Runtime::new()?;
-    rt.block_on(async { run().await })
+    let result = rt.block_on(async { run().await });
+    // Don't wait for the blocking stdin read behind tokio::io::stdin
+    rt.shutdown_background();
+    logger::flush();
+    result
 }
 
 /// Handle pass-through mode for Roslyn arguments (--version, --help, etc.)
-async fn handle_passthrough_mode(args: &[String]) -> io::Result<()> {
+async fn handle_passthrough_mode(args: &[String]) -> io::Result<ExitCode> {
     logger::info("[roslyn_wrapper] Pass-through mode: forwarding arguments to Roslyn");
 
     // Download/find Roslyn first
@@ -104,7 +112,13 @@ async fn handle_passthrough_mode(args: &[String]) -> io::Result<()> {
     // Execute Roslyn with the provided arguments
     let status = Command::new(roslyn_path).args(&args[1..]).status()?;
 
-    std::process::exit(status.code().unwrap_or(1));
+    Ok(ExitCode::from(exit_code(status.code())))
+}
+
+/// Roslyn's exit code as ours; codes that don't fit, such as large Windows codes, and a
+/// missing one (killed by a signal) are a plain failure rather than wrapping around
+fn exit_code(code: Option<i32>) -> u8 {
+    code.and_then(|code| u8::try_from(code).ok()).unwrap_or(1)
 }
 
 /// Resolve the Roslyn LSP binary path from arguments or download
@@ -170,8 +184,53 @@ async fn get_roslyn_lsp_path(args: &[String]) -> io::Result<String> {
     }
 }
 
-async fn run() -> io::Result<()> {
-    let args: Vec<String> = std::env::args().collect();
+async fn run() -> io::Result<ExitCode> {
+    let mut args: Vec<String> = std::env::args().collect();
+
+    // `replay <file>` plays a recorded session instead of serving an editor
+    if args.get(1).map(String::as_str) == Some("replay") {
+        return recording::replay(&args[2..]).await;
+    }
+
+    let transport = match take_option(&mut args, "--roslyn-transport")? {
+        Some(value) => RoslynTransport::parse(&value).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown Roslyn transport: {value} (expected 'stdio' or 'pipe')"),
+            )
+        })?,
+        None => RoslynTransport::Stdio,
+    };
+
+    let listen_addr = match take_option(&mut args, "--listen")? {
+        Some(value) => Some(ListenAddr::parse(&value).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid listen address: {value} (expected tcp:PORT or unix:PATH)"),
+            )
+        })?),
+        None => None,
+    };
+
+    let daemon = take_flag(&mut args, "--daemon");
+    let razor = take_flag(&mut args, "--razor");
+    let daemon_host = take_option(&mut args, "--daemon-host")?.map(PathBuf::from);
+    let record = take_option(&mut args, "--record")?.map(PathBuf::from);
+    let idle_timeout = match take_option(&mut args, "--idle-timeout")? {
+        Some(value) => value.parse::<u64>().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid idle timeout: {value} (expected seconds)"),
+            )
+        })?,
+        None => daemon::DEFAULT_IDLE_TIMEOUT_SECS,
+    };
+    let mut settings = Vec::new();
+    while let Some(setting) = take_option(&mut args, "--set")? {
+        settings.push(setting);
+    }
+    let command_line_config =
+        config::command_line(&settings).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
 
     // Check if we should pass through arguments to Roslyn (e.g., --version, --help)
     if args.len() > 1 {
@@ -183,22 +242,106 @@ async fn run() -> io::Result<()> {
         }
     }
 
+    // Until the editor's `initialize` adds its options and names the workspace
+    let startup_config = config::load(std::env::current_dir().ok().as_deref(), &command_line_config, None);
+    startup_config.apply_logging();
+    startup_config.log_effective();
+
+    if daemon {
+        #[cfg(unix)]
+        {
+            // Everything the host needs to start Roslyn the same way this wrapper would
+            let mut host_args = vec![
+                "--idle-timeout".to_string(),
+                idle_timeout.to_string(),
+                "--roslyn-transport".to_string(),
+                transport.as_str().to_string(),
+            ];
+            if razor {
+                host_args.push("--razor".to_string());
+            }
+            if let Some(record) = &record {
+                host_args.push("--record".to_string());
+                host_args.push(record.display().to_string());
+            }
+            for setting in &settings {
+                host_args.push("--set".to_string());
+                host_args.push(setting.clone());
+            }
+            host_args.extend(args.iter().skip(1).cloned());
+            daemon::run_client(BufReader::new(tokio::io::stdin()), &host_args).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        #[cfg(not(unix))]
+        logger::warn("[roslyn_wrapper] Daemon mode is only supported on Unix; running standalone");
+    }
+
+    let listen_addr = match &daemon_host {
+        Some(socket) => {
+            #[cfg(unix)]
+            if daemon::is_running(socket) {
+                logger::info("[roslyn_wrapper] Roslyn daemon already running for this solution");
+                return Ok(ExitCode::SUCCESS);
+            }
+            Some(ListenAddr::Unix(socket.clone()))
+        }
+        None => listen_addr,
+    };
+
+    // Bind before starting Roslyn so a busy address fails fast
+    let listener = match &listen_addr {
+        Some(addr) => {
+            let listener = Listener::bind(addr).await.map_err(|e| {
+                logger::error(format!("[roslyn_wrapper] Failed to listen on {addr}: {e}"));
+                e
+            })?;
+            logger::info(format!("[roslyn_wrapper] Listening for clients on {addr}"));
+            Some(listener)
+        }
+        None => None,
+    };
+
+    if let Some(path) = &record {
+        recording::start(path).map_err(|e| {
+            logger::error(format!("[roslyn_wrapper] Failed to start recording to {}: {e}", path.display()));
+            e
+        })?;
+        logger::info(format!("[roslyn_wrapper] Recording the session to {}", path.display()));
+    }
+
     // LSP proxy mode: Get Roslyn LSP path from command-line arguments or download
     let roslyn_path_str = get_roslyn_lsp_path(&args).await?;
 
+    // Razor mode: Roslyn hosts the Razor language features itself (cohosting)
+    let razor_args = if razor {
+        match download::get_razor_assets(Path::new(&roslyn_path_str)).await {
+            Ok(assets) => assets.roslyn_args(),
+            Err(e) => {
+                logger::warn(format!("[roslyn_wrapper] Razor support unavailable: {e}"));
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
     logger::info(format!(
-        "[roslyn_wrapper] Starting Roslyn process: {roslyn_path_str}"
+        "[roslyn_wrapper] Starting Roslyn process: {roslyn_path_str} (transport: {transport:?}, razor: {})",
+        !razor_args.is_empty()
     ));
 
     // Start Roslyn subprocess
-    let mut roslyn_process = Command::new(&roslyn_path_str)
+    let mut roslyn_command = tokio::process::Command::new(&roslyn_path_str);
+    lifecycle::bind_to_wrapper(&mut roslyn_command);
+    let mut roslyn_process = roslyn_command
         .args([
             "--extensionLogDirectory",
             ".",
             "--logLevel",
             "Information",
-            "--stdio",
+            transport.roslyn_arg(),
         ])
+        .args(&razor_args)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -224,364 +367,117 @@ async fn run() -> io::Result<()> {
 
     logger::info("[roslyn_wrapper] Roslyn process started successfully");
 
-    // Wrap in Arc<Mutex<>> for sharing between tasks
-    let roslyn_stdin = Arc::new(Mutex::new(roslyn_stdin));
-    let mut roslyn_stdout = BufReader::new(roslyn_stdout);
-
-    // Create stdout early so it can be cloned for stderr task
-    let stdin = io::stdin();
-    let mut stdin = BufReader::new(stdin);
-    let stdout = Arc::new(Mutex::new(io::stdout()));
-
     // Pipe Roslyn stderr to wrapper logs for debugging
-    let mut roslyn_stderr_reader = BufReader::new(roslyn_stderr);
-    let _stderr_task = tokio::task::spawn_blocking(move || {
-        let mut line = String::new();
-        loop {
-            line.clear();
-            match roslyn_stderr_reader.read_line(&mut line) {
-                Ok(0) => break,
-                Ok(_) => {
-                    let msg = line.trim_end();
-                    if !msg.is_empty() {
-                        logger::debug(format!("[roslyn][stderr] {msg}"));
-                    }
-                }
-                Err(_) => break,
-            }
+    let _stderr_task = spawn_output_logger(BufReader::new(roslyn_stderr), "stderr");
+
+    // In pipe mode Roslyn's stdin is unused but must stay open for the session
+    let mut _roslyn_stdin_keepalive = None;
+    let (roslyn_reader, roslyn_writer): (LspReader, LspWriter) = match transport {
+        RoslynTransport::Stdio => (Box::new(BufReader::new(roslyn_stdout)), Box::new(roslyn_stdin)),
+        RoslynTransport::Pipe => {
+            // Roslyn announces the pipe on stdout; after that stdout is only used for logs
+            let mut stdout_reader = BufReader::new(roslyn_stdout);
+            let pipe_name = transport::read_pipe_name(&mut stdout_reader).await.map_err(|e| {
+                logger::error(format!("[roslyn_wrapper] Failed to read Roslyn pipe name: {e}"));
+                e
+            })?;
+            logger::info(format!("[roslyn_wrapper] Connecting to Roslyn pipe: {pipe_name}"));
+            let connection = transport::connect_pipe(&pipe_name).await.map_err(|e| {
+                logger::error(format!("[roslyn_wrapper] Failed to connect to Roslyn pipe: {e}"));
+                e
+            })?;
+            let _stdout_task = spawn_output_logger(stdout_reader, "stdout");
+            _roslyn_stdin_keepalive = Some(roslyn_stdin);
+            connection
         }
-    });
+    };
 
-    // Shared state for initialization
-    let initialized = Arc::new(Mutex::new(false));
-    let solution_uri: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
-    let workspace_roots: Arc<Mutex<Vec<PathBuf>>> = Arc::new(Mutex::new(Vec::new()));
-
-    // Track request IDs to methods to normalize responses when needed
-    let id_method_map: Arc<Mutex<HashMap<String, String>>> = Arc::new(Mutex::new(HashMap::new()));
+    let mut proxy = Proxy::new(roslyn_writer, listener.is_some()).with_command_line_config(command_line_config);
+    let concurrent_clients = daemon_host.is_some();
+    if concurrent_clients {
+        // The daemon host stops once it has had no clients for the idle timeout
+        proxy = proxy.with_idle_timeout(Duration::from_secs(idle_timeout));
+    }
+    let handle = proxy.handle();
 
     logger::debug("[roslyn_wrapper] Starting bidirectional message forwarding");
 
-    // Spawn task to forward messages from client to Roslyn
-    let roslyn_stdin_clone = Arc::clone(&roslyn_stdin);
-    let solution_uri_clone = Arc::clone(&solution_uri);
-    let workspace_roots_c2r = Arc::clone(&workspace_roots);
-    let id_method_map_c2r = Arc::clone(&id_method_map);
-
-    let client_to_roslyn = tokio::task::spawn_blocking(move || {
-        loop {
-            match read_lsp_message(&mut stdin) {
-                Ok(Some(msg)) => {
-                    logger::debug("[roslyn_wrapper] <== FROM CLIENT");
-
-                    // Record request method by id for response normalization
-                    if let Some(id_val) = msg.get("id") {
-                        if let Some(method) = msg.get("method").and_then(|v| v.as_str()) {
-                            // Only track a few methods we may normalize
-                            let should_track = matches!(method, "textDocument/diagnostic");
-                            if should_track {
-                                let mut map = id_method_map_c2r.blocking_lock();
-                                map.insert(id_val.to_string(), method.to_string());
-                            }
-                        }
-                    }
-
-                    // Check for initialize request to extract solution URI
-                    if let Some(method) = msg.get("method").and_then(|v| v.as_str()) {
-                        if method == "initialize" {
-                            if let Some(params) = msg.get("params") {
-                                // capture workspace rootUri if present
-                                if let Some(root_uri) =
-                                    params.get("rootUri").and_then(|v| v.as_str())
-                                {
-                                    if let Ok(path) = path_utils::url_to_path(root_uri) {
-                                        let mut roots = workspace_roots_c2r.blocking_lock();
-                                        roots.clear();
-                                        roots.push(path);
-                                        logger::info("[roslyn_wrapper] Captured workspace rootUri");
-                                    }
-                                }
-                                // capture workspaceFolders if present
-                                if let Some(folders) =
-                                    params.get("workspaceFolders").and_then(|v| v.as_array())
-                                {
-                                    let mut roots = workspace_roots_c2r.blocking_lock();
-                                    if roots.is_empty() {
-                                        for f in folders {
-                                            if let Some(uri) = f.get("uri").and_then(|u| u.as_str())
-                                            {
-                                                if let Ok(p) = path_utils::url_to_path(uri) {
-                                                    roots.push(p);
-                                                }
-                                            }
-                                        }
-                                        if !roots.is_empty() {
-                                            logger::info(
-                                                "[roslyn_wrapper] Captured workspaceFolders",
-                                            );
-                                        }
-                                    }
-                                }
-                                if let Some(init_opts) = params.get("initializationOptions") {
-                                    // Capture logging configuration
-                                    if let Some(log_level) = init_opts.get("logLevel").and_then(|v| v.as_str()) {
-                                        crate::logger::configure(Some(log_level), None, None);
-                                    }
-                                    if let Some(log_file) = init_opts.get("logFile").and_then(|v| v.as_str()) {
-                                        crate::logger::configure(None, Some(log_file), None);
-                                    } else if let Some(log_dir) = init_opts.get("logDirectory").and_then(|v| v.as_str()) {
-                                        crate::logger::configure(None, None, Some(log_dir));
-                                    }
-
-                                    if let Some(solution) = init_opts.get("solution").and_then(|v| v.as_str()) {
-                                        let mut sol_uri = solution_uri_clone.blocking_lock();
-                                        *sol_uri = Some(solution.to_string());
-                                        logger::info("[roslyn_wrapper] Found solution URI");
-                                    }
-                                }
-
-                            }
-                        }
+    // Feed client connection(s) into the router
+    let clients = tokio::spawn(async move {
+        match listener {
+            None => {
+                let stdin: LspReader = Box::new(BufReader::new(tokio::io::stdin()));
+                handle.serve_client(stdin, Box::new(tokio::io::stdout())).await;
+            }
+            Some(listener) => loop {
+                // Listen mode serves clients one at a time, the daemon host all at once;
+                // Roslyn stays warm in between
+                match listener.accept().await {
+                    Ok((reader, writer)) if concurrent_clients => {
+                        let handle = handle.clone();
+                        tokio::spawn(async move { handle.serve_client(reader, writer).await });
                     }
-
-                    // Forward to Roslyn
-                    let mut roslyn_stdin = roslyn_stdin_clone.blocking_lock();
-                    if let Err(e) = send_lsp_message(&mut *roslyn_stdin, &msg) {
-                        logger::error(format!("[roslyn_wrapper] Error forwarding to Roslyn: {e}"));
+                    Ok((reader, writer)) => handle.serve_client(reader, writer).await,
+                    Err(e) => {
+                        logger::error(format!("[roslyn_wrapper] Error accepting client: {e}"));
                         break;
                     }
-                    logger::debug("[roslyn_wrapper] ==> TO ROSLYN");
-                }
-                Ok(None) => {
-                    logger::info("[roslyn_wrapper] Client closed connection");
-                    break;
                 }
-                Err(e) => {
-                    logger::error(format!("[roslyn_wrapper] Error reading from client: {e}"));
-                    break;
-                }
-            }
+            },
         }
     });
 
-    // Main task: forward messages from Roslyn to client
-    let id_method_map_r2c = Arc::clone(&id_method_map);
-    let workspace_roots_r2c = Arc::clone(&workspace_roots);
-    let stdout_r2c = Arc::clone(&stdout);
-    let roslyn_to_client = tokio::task::spawn_blocking(move || {
-        loop {
-            match read_lsp_message(&mut roslyn_stdout) {
-                Ok(Some(mut msg)) => {
-                    logger::debug("[roslyn_wrapper] <== FROM ROSLYN");
-
-                    // Normalize certain server->client requests with unit params
-                    let method_opt = msg
-                        .get("method")
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string());
-                    if let Some(method) = method_opt {
-                        if matches!(
-                            method.as_str(),
-                            "workspace/inlayHint/refresh"
-                                | "workspace/diagnostic/refresh"
-                                | "workspace/codeLens/refresh"
-                        ) {
-                            let needs_fix = match msg.get("params") {
-                                None => true,
-                                Some(v) if !v.is_object() => true, // [] or null → {}
-                                _ => false,
-                            };
-                            if needs_fix {
-                                if let Some(obj) = msg.as_object_mut() {
-                                    obj.remove("params");
-                                    logger::debug(format!(
-                                        "[roslyn_wrapper] Removed params for unit method {method}"
-                                    ));
-                                }
-                            }
-                        }
-                    }
+    // SIGTERM/SIGINT stop Roslyn the same way a client's shutdown/exit would
+    let signal_handle = proxy.handle();
+    let signals = tokio::spawn(async move {
+        let signal = lifecycle::shutdown_signal().await;
+        logger::info(format!("[roslyn_wrapper] Received {signal}; shutting down"));
+        signal_handle.shutdown().await;
+    });
 
-                    // Check if this is initialization response
-                    if let Some(result) = msg.get("result") {
-                        if result.get("capabilities").is_some() {
-                            let mut init = initialized.blocking_lock();
-                            if !*init {
-                                *init = true;
-                                logger::info("[roslyn_wrapper] Initialization complete");
-
-                                // Forward response to client first
-                                let mut stdout_lock = stdout.blocking_lock();
-                                if let Err(e) = send_lsp_message(&mut *stdout_lock, &msg) {
-                                    logger::error(format!(
-                                        "[roslyn_wrapper] Error forwarding to client: {e}"
-                                    ));
-                                    break;
-                                }
-                                logger::debug("[roslyn_wrapper] ==> TO CLIENT");
-
-                                drop(stdout_lock); // Release lock
-
-                                // Then send solution/open notification
-                                let sol_uri = solution_uri.blocking_lock();
-                                let maybe_solution = if sol_uri.is_some() {
-                                    sol_uri.clone()
-                                } else {
-                                    // attempt discovery from all workspace roots (rootUri and workspaceFolders)
-                                    let roots = workspace_roots_r2c.blocking_lock();
-                                    let mut found: Option<String> = None;
-                                    for r in roots.iter() {
-                                        if let Some(uri) =
-                                            path_utils::try_find_solution_or_project(r)
-                                        {
-                                            found = Some(uri);
-                                            break;
-                                        }
-                                    }
-                                    found
-                                };
-                                if let Some(uri) = maybe_solution {
-                                    let notification = json!({
-                                        "jsonrpc": "2.0",
-                                        "method": "solution/open",
-                                        "params": {
-                                            "solution": uri
-                                        }
-                                    });
-                                    logger::info(
-                                        "[roslyn_wrapper] Sending solution/open notification",
-                                    );
-                                    let mut roslyn_stdin = roslyn_stdin.blocking_lock();
-                                    if let Err(e) =
-                                        send_lsp_message(&mut *roslyn_stdin, &notification)
-                                    {
-                                        logger::error(format!(
-                                            "[roslyn_wrapper] Error sending solution/open: {e}"
-                                        ));
-                                    }
-                                } else {
-                                    logger::info(
-                                        "[roslyn_wrapper] No solution or project found to open",
-                                    );
-                                    // Inform the client so users understand why features are limited
-                                    let info_msg = json!({
-                                        "jsonrpc": "2.0",
-                                        "method": "window/showMessage",
-                                        "params": {
-                                            "type": LSP_MESSAGE_TYPE_WARNING,
-                                            "message": "No .sln or .csproj found in the workspace. C# features are limited until a solution or project is opened. Open a folder with a .sln/.csproj or configure the 'solution' option in the C# extension."
-                                        }
-                                    });
-                                    let mut stdout_lock = stdout.blocking_lock();
-                                    if let Err(e) = send_lsp_message(&mut *stdout_lock, &info_msg) {
-                                        logger::error(format!("[roslyn_wrapper] Failed to send no-solution warning: {e}"));
-                                    }
-                                }
-
-                                continue; // Already forwarded, skip duplicate
-                            }
-                        }
-                    }
+    // Runs until the client exits or leaves, Roslyn exits, a signal arrives or the daemon goes idle
+    let clean_session = proxy.run(roslyn_reader).await;
+    clients.abort();
+    signals.abort();
 
-                    // Normalize null results for known requests (e.g., textDocument/diagnostic)
-                    if let Some(id_val) = msg.get("id") {
-                        let id_key = id_val.to_string();
-                        let tracked_method = {
-                            let mut map = id_method_map_r2c.blocking_lock();
-                            map.remove(&id_key)
-                        };
-                        if let Some(method) = tracked_method {
-                            if method == "textDocument/diagnostic" {
-                                let need_fix = match msg.get("result") {
-                                    None => true,
-                                    Some(v) if v.is_null() => true,
-                                    _ => false,
-                                };
-                                if need_fix {
-                                    if let Some(obj) = msg.as_object_mut() {
-                                        obj.insert(
-                                            "result".to_string(),
-                                            json!({
-                                                "kind": "full",
-                                                "items": []
-                                            }),
-                                        );
-                                        logger::debug("[roslyn_wrapper] Normalized null diagnostic result to empty report");
-                                    }
-                                }
-                            }
-                        }
-                    }
+    let clean_exit = lifecycle::wait_or_kill(&mut roslyn_process, lifecycle::ROSLYN_EXIT_TIMEOUT).await;
 
-                    // Map Roslyn custom toast notifications to standard LSP showMessage
-                    let forward_msg = if let Some(method_name) =
-                        msg.get("method").and_then(|v| v.as_str())
-                    {
-                        if method_name == "window/_roslyn_showToast" {
-                            if let Some(params) = msg.get("params") {
-                                let message =
-                                    params.get("message").and_then(|v| v.as_str()).unwrap_or("");
-                                let roslyn_type = params
-                                    .get("messageType")
-                                    .and_then(|v| v.as_i64())
-                                    .unwrap_or(ROSLYN_MESSAGE_TYPE_INFO);
-                                // Map Roslyn message types to LSP: 3->1 (Error), 1->2 (Warning), 2->3 (Info)
-                                let lsp_type = match roslyn_type {
-                                    ROSLYN_MESSAGE_TYPE_ERROR => LSP_MESSAGE_TYPE_ERROR,
-                                    ROSLYN_MESSAGE_TYPE_WARNING => LSP_MESSAGE_TYPE_WARNING,
-                                    ROSLYN_MESSAGE_TYPE_INFO => LSP_MESSAGE_TYPE_INFO,
-                                    _ => LSP_MESSAGE_TYPE_INFO,
-                                };
-
-                                logger::debug("[roslyn_wrapper] Rewriting _roslyn_showToast to window/showMessage");
-                                json!({
-                                    "jsonrpc": "2.0",
-                                    "method": "window/showMessage",
-                                    "params": {
-                                        "type": lsp_type,
-                                        "message": message
-                                    }
-                                })
-                            } else {
-                                msg
-                            }
-                        } else {
-                            msg
-                        }
-                    } else {
-                        msg
-                    };
-
-                    // Forward to client
-                    let mut stdout = stdout_r2c.blocking_lock();
-                    if let Err(e) = send_lsp_message(&mut *stdout, &forward_msg) {
-                        logger::error(format!("[roslyn_wrapper] Error forwarding to client: {e}"));
-                        break;
-                    }
-                    logger::debug("[roslyn_wrapper] ==> TO CLIENT");
-                }
-                Ok(None) => {
-                    logger::info("[roslyn_wrapper] Roslyn closed connection");
-                    break;
-                }
-                Err(e) => {
-                    logger::error(format!("[roslyn_wrapper] Error reading from Roslyn: {e}"));
-                    break;
-                }
-            }
-        }
-    });
+    logger::info("[roslyn_wrapper] Shutting down");
+    if clean_session && clean_exit {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
 
-    // Wait for either task to complete (which means connection closed)
-    tokio::select! {
-        _ = client_to_roslyn => {
-            logger::debug("[roslyn_wrapper] Client to Roslyn task completed");
-        }
-        _ = roslyn_to_client => {
-            logger::debug("[roslyn_wrapper] Roslyn to Client task completed");
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|a| a.to_string()).collect()
     }
 
-    logger::info("[roslyn_wrapper] Shutting down");
-    Ok(())
+    #[test]
+    fn exit_codes_that_do_not_fit_are_failures() {
+        assert_eq!(exit_code(Some(0)), 0);
+        assert_eq!(exit_code(Some(3)), 3);
+        assert_eq!(exit_code(Some(256)), 1);
+        assert_eq!(exit_code(Some(-1073741819)), 1);
+        assert_eq!(exit_code(None), 1);
+    }
+
+    #[test]
+    fn options_take_their_value_or_fail_without_one() {
+        let mut given = args(&["roslyn-wrapper", "--set=logLevel=debug", "--record", "out.jsonl", "--logLevel"]);
+        assert_eq!(take_option(&mut given, "--set").unwrap().as_deref(), Some("logLevel=debug"));
+        assert_eq!(take_option(&mut given, "--record").unwrap().as_deref(), Some("out.jsonl"));
+        assert_eq!(take_option(&mut given, "--listen").unwrap(), None);
+        assert_eq!(given, ["roslyn-wrapper", "--logLevel"]);
+
+        let mut given = args(&["roslyn-wrapper", "--roslyn-transport"]);
+        let err = take_option(&mut given, "--roslyn-transport").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("--roslyn-transport"));
+    }
 }
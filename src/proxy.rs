@@ -0,0 +1,2461 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{Instrument, Span};
+
+use crate::commands::{self, Command, FixAll};
+use crate::config;
+use crate::custom::{self, Handler, Local};
+use crate::loading::{self, HoldRequests, LoadProgress};
+use crate::{recording, restore};
+use crate::timeouts::RequestTimeouts;
+use crate::transport::{LspReader, LspWriter};
+use crate::lsp::{self, read_lsp_message, write_message, Incoming, Message};
+use crate::lsp::{LSP_MESSAGE_TYPE_INFO, LSP_MESSAGE_TYPE_WARNING};
+use crate::logger::{self, Direction};
+use crate::{lifecycle, path_utils};
+
+// JSON-RPC error code for server requests that no client can answer
+const JSONRPC_REQUEST_FAILED: i64 = -32803;
+
+// JSON-RPC error code for a command with arguments the wrapper cannot use
+const JSONRPC_INVALID_PARAMS: i64 = -32602;
+
+// JSON-RPC error code for custom Roslyn requests nobody implements
+const JSONRPC_METHOD_NOT_FOUND: i64 = -32601;
+
+// JSON-RPC error codes for requests the wrapper gave up on after a timeout
+const JSONRPC_REQUEST_CANCELLED: i64 = -32800;
+const JSONRPC_SERVER_CANCELLED: i64 = -32802;
+
+// Answers slower than this are logged
+const SLOW_REQUEST_THRESHOLD: Duration = Duration::from_secs(2);
+
+// Action offered in the restore prompt when automatic restore is off
+const RESTORE_ACTION: &str = "Restore";
+
+// Events queued for the router before a reader has to wait
+const CHANNEL_CAPACITY: usize = 64;
+
+// Bytes queued for a client that isn't reading before it is disconnected
+const MAX_CLIENT_BACKLOG: usize = 64 * 1024 * 1024;
+
+// How long shutdown waits for queued messages to be written out
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Id of the wrapper's own `shutdown` request, and how long Roslyn gets to answer it
+// before it is told to exit anyway
+const SHUTDOWN_ID: &str = "roslyn-wrapper/shutdown";
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub type ClientId = u64;
+
+/// Input to the router. Every connection feeds the same queue, so the session
+/// state has exactly one owner and needs no locks.
+enum Event {
+    ClientConnected {
+        client: ClientId,
+        outbox: Outbox,
+        writer: JoinHandle<()>,
+    },
+    FromClient(ClientId, Incoming),
+    ClientDisconnected(ClientId),
+    /// The editor process a client named in `initialize` (`processId`) is gone
+    EditorExited(ClientId, u32),
+    FromRoslyn(Incoming),
+    RoslynClosed,
+    /// A request timeout or the end of a request's hold has passed
+    RequestsDue,
+    /// Output or completion of the running `dotnet restore`
+    Restore(restore::Update),
+    /// The wrapper itself is asked to stop (SIGTERM/SIGINT)
+    Shutdown,
+    /// A wrapper log entry to mirror to the editor
+    Log(logger::ClientEntry),
+}
+
+/// Router for one Roslyn session.
+///
+/// Reader tasks turn each connection into events on one bounded queue, the router
+/// applies them to the session state, and writer tasks drain an outbox per
+/// connection. A reader waits while the router is busy, but the router never waits
+/// on an outbox: a client that stops reading is disconnected once its backlog passes
+/// [`MAX_CLIENT_BACKLOG`], so it can't stall the other clients or Roslyn. Messages travel as the bytes that were read and are only
+/// parsed in full when one of the rewrite rules below applies.
+///
+/// In persistent mode (listen/daemon) Roslyn outlives individual client connections:
+/// clients attach and detach around it, a late client gets the cached `initialize`
+/// result instead of re-initializing Roslyn, and several clients can share the
+/// session at once. Client request ids are then rewritten so responses can be
+/// routed back to the client that asked.
+pub struct Proxy {
+    persistent: bool,
+    idle_timeout: Option<Duration>,
+    // `--set` options, layered under each client's `initializationOptions`
+    command_line: Map<String, Value>,
+    roslyn: Outbox,
+    roslyn_writer: Option<JoinHandle<()>>,
+    outboxes: HashMap<ClientId, Outbox>,
+    // Clients over `max_client_backlog`, to be dropped once the current event is handled
+    stalled_clients: Vec<ClientId>,
+    max_client_backlog: usize,
+    client_writers: HashMap<ClientId, JoinHandle<()>>,
+    events: mpsc::Receiver<Event>,
+    client_log: mpsc::UnboundedReceiver<logger::ClientEntry>,
+    // Entries for the editor logged while no client was attached, for the next one
+    log_backlog: VecDeque<logger::ClientEntry>,
+    // Whether this router configures the process-wide logger; test routers leave it alone
+    configures_logger: bool,
+    handle: ProxyHandle,
+    state: SessionState,
+}
+
+/// Cheap, cloneable way to connect clients to a running [`Proxy`]
+#[derive(Clone)]
+pub struct ProxyHandle {
+    events: mpsc::Sender<Event>,
+    next_client_id: Arc<AtomicU64>,
+}
+
+#[derive(Default)]
+struct SessionState {
+    initialized: bool,
+    initialize_in_flight: bool,
+    init_result: Option<Value>,
+    solution_uri: Option<String>,
+    workspace_roots: Vec<PathBuf>,
+    // Client requests Roslyn has not answered yet, keyed by the id Roslyn sees
+    requests: HashMap<String, InFlight>,
+    request_timeouts: RequestTimeouts,
+    // Requests to hold while the solution loads, if enabled
+    hold_requests: Option<HoldRequests>,
+    // Client requests waiting for the solution to load
+    held_requests: Vec<HeldRequest>,
+    // From `solution/open` until Roslyn sends `workspace/projectInitializationComplete`
+    solution_load: Option<SolutionLoad>,
+    // Ask before running `dotnet restore` (initializationOptions.autoRestore = false)
+    ask_before_restore: bool,
+    // Restores waiting for the running one to finish
+    restore_queue: VecDeque<RestoreRequest>,
+    restore: Option<RunningRestore>,
+    // Active `client/registerCapability` registrations, replayed to late clients
+    registrations: Vec<Value>,
+    next_request_id: u64,
+    clients: HashMap<ClientId, ClientState>,
+    // Clients whose `initialize` arrived while Roslyn was still initializing
+    initialize_waiters: Vec<(ClientId, Value)>,
+    // Clients that have each document open; Roslyn sees one open per document
+    document_owners: HashMap<String, HashSet<ClientId>>,
+    // Last version Roslyn saw for each open document; clients number their edits apart
+    document_versions: HashMap<String, i64>,
+    idle_since: Option<Instant>,
+    // The client asked Roslyn to shut down; an `exit` after this is a clean exit
+    shutdown_requested: bool,
+    // Roslyn closed its end while the wrapper waited for its reply to `shutdown`
+    roslyn_closed: bool,
+    // Logging settings applied by the session's first `initialize`; later clients keep them
+    settings: Option<config::WrapperConfig>,
+}
+
+/// Per-connection bookkeeping
+#[derive(Default)]
+struct ClientState {
+    reattached: bool,
+    // Roslyn requests forwarded to the client and not answered yet
+    pending_server_requests: HashSet<String>,
+    // Requests the wrapper sent to the client itself, by id
+    wrapper_requests: HashMap<String, WrapperRequest>,
+    // The client accepts server-initiated `$/progress`
+    work_done_progress: bool,
+    // The client supports `window/showDocument`
+    show_document: bool,
+    // Watches the editor process from `initialize`
+    editor_watch: Option<JoinHandle<()>>,
+}
+
+/// A client request forwarded to Roslyn
+struct InFlight {
+    client: ClientId,
+    // The id the client used; differs from the Roslyn-side id in persistent mode
+    client_id: Value,
+    roslyn_id: Value,
+    method: String,
+    started: Instant,
+    deadline: Option<Instant>,
+    // The client command this request runs for; its answer is handled by the wrapper
+    command: Option<Command>,
+    // Entries logged while handling the request or its answer carry its method and id
+    span: Span,
+}
+
+/// Requests the wrapper sends to a client itself; their answers never reach Roslyn
+enum WrapperRequest {
+    /// Replays dynamic registrations to a reattached client
+    RegisterCapability,
+    /// Creates the token for a progress indicator
+    CreateProgress(String),
+    /// Asks whether to run `dotnet restore`
+    RestorePrompt(RestoreRequest),
+    /// Asks where to apply a fix-all; `id` is the client's `workspace/executeCommand`
+    FixAllScope { id: Value, fix_all: FixAll },
+    /// Applies the edit a client command produced
+    ApplyEdit,
+    /// Opens a location in the editor
+    ShowDocument,
+}
+
+impl WrapperRequest {
+    fn method(&self) -> &'static str {
+        match self {
+            WrapperRequest::RegisterCapability => "client/registerCapability",
+            WrapperRequest::CreateProgress(_) => "window/workDoneProgress/create",
+            WrapperRequest::RestorePrompt(_) | WrapperRequest::FixAllScope { .. } => "window/showMessageRequest",
+            WrapperRequest::ApplyEdit => "workspace/applyEdit",
+            WrapperRequest::ShowDocument => "window/showDocument",
+        }
+    }
+}
+
+/// Projects Roslyn asked to restore, and its request to answer once they are
+struct RestoreRequest {
+    roslyn_id: Option<Value>,
+    projects: Vec<String>,
+}
+
+/// The `dotnet restore` currently running
+struct RunningRestore {
+    request: RestoreRequest,
+    task: JoinHandle<()>,
+    // Client and token of the progress indicator, and whether the token was accepted
+    progress: Option<(ClientId, String)>,
+    progress_shown: bool,
+}
+
+/// A client request held back until the solution has loaded
+struct HeldRequest {
+    client: ClientId,
+    method: String,
+    msg: Message,
+    release_at: Instant,
+}
+
+/// Roslyn is loading the solution sent in `solution/open`
+struct SolutionLoad {
+    solution: String,
+    started: Instant,
+    progress: LoadProgress,
+    // Client offered the progress indicator, and whether it accepted the token
+    progress_client: Option<ClientId>,
+    progress_shown: bool,
+}
+
+enum ClientFlow {
+    Forward(Message),
+    /// Forward the message, then end the session (`exit` in standalone mode)
+    ForwardAndEnd(Message),
+    Handled,
+    Disconnect,
+}
+
+fn id_key(id: &Value) -> String {
+    id.to_string()
+}
+
+/// The span of a client request, as the client knows it
+fn request_span(method: &str, id: &Value) -> Span {
+    tracing::info_span!(target: logger::WRAPPER_TARGET, "request", method, id = %id)
+}
+
+/// Messages waiting for one connection's writer, and how many bytes they add up to
+#[derive(Clone)]
+struct Outbox {
+    sender: mpsc::UnboundedSender<Message>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl Outbox {
+    fn new() -> (Self, mpsc::UnboundedReceiver<Message>) {
+        let (sender, queue) = mpsc::unbounded_channel();
+        (Self { sender, queued: Arc::new(AtomicUsize::new(0)) }, queue)
+    }
+
+    /// Queue a message without waiting; `false` once the writer is gone
+    fn push(&self, msg: Message) -> bool {
+        let len = msg.body().len();
+        self.queued.fetch_add(len, Ordering::Relaxed);
+        if self.sender.send(msg).is_err() {
+            self.queued.fetch_sub(len, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    fn backlog(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+}
+
+/// Write queued messages to one connection until its outbox is closed
+fn spawn_writer(
+    mut writer: LspWriter,
+    mut queue: mpsc::UnboundedReceiver<Message>,
+    queued: Arc<AtomicUsize>,
+    peer: String,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(msg) = queue.recv().await {
+            if let Err(e) = write_message(&mut writer, &msg).await {
+                logger::error(format!("[roslyn_wrapper] Error forwarding to {peer}: {e}"));
+                return;
+            }
+            queued.fetch_sub(msg.body().len(), Ordering::Relaxed);
+        }
+        let _ = writer.shutdown().await;
+    })
+}
+
+/// Feed Roslyn's output into the router until Roslyn closes the connection
+async fn read_roslyn(mut reader: LspReader, events: mpsc::Sender<Event>) {
+    loop {
+        match read_lsp_message(&mut reader).await {
+            Ok(Some(incoming)) => {
+                if events.send(Event::FromRoslyn(incoming)).await.is_err() {
+                    return;
+                }
+            }
+            Ok(None) => {
+                logger::info("[roslyn_wrapper] Roslyn closed connection");
+                break;
+            }
+            Err(e) => {
+                logger::error(format!("[roslyn_wrapper] Error reading from Roslyn: {e}"));
+                break;
+            }
+        }
+    }
+    let _ = events.send(Event::RoslynClosed).await;
+}
+
+/// Turn away an event that arrives once Roslyn is being stopped
+fn discard_while_stopping(event: Event) {
+    match event {
+        Event::ClientConnected { client, outbox, .. } => {
+            logger::info(format!("[roslyn_wrapper] Roslyn is shutting down; closing the connection of client {client}"));
+            // Without its outbox the writer finishes and closes the connection
+            drop(outbox);
+        }
+        Event::FromClient(client, _) => {
+            logger::debug(format!("[roslyn_wrapper] Roslyn is shutting down; dropped a message from client {client}"));
+        }
+        _ => {}
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+impl ProxyHandle {
+    fn next_client(&self) -> ClientId {
+        self.next_client_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Forward messages from one client to the router until it disconnects
+    pub async fn serve_client(&self, mut reader: LspReader, writer: LspWriter) {
+        let client = self.next_client();
+        let (outbox, queue) = Outbox::new();
+        let writer = spawn_writer(writer, queue, outbox.queued.clone(), format!("client {client}"));
+        let connected = Event::ClientConnected { client, outbox, writer };
+        if self.events.send(connected).await.is_err() {
+            return;
+        }
+
+        loop {
+            match read_lsp_message(&mut reader).await {
+                Ok(Some(incoming)) => {
+                    // Waits while the router is busy, which throttles a flooding client
+                    if self.events.send(Event::FromClient(client, incoming)).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => {
+                    logger::info("[roslyn_wrapper] Client closed connection");
+                    break;
+                }
+                Err(e) => {
+                    logger::error(format!("[roslyn_wrapper] Error reading from client: {e}"));
+                    break;
+                }
+            }
+        }
+        let _ = self.events.send(Event::ClientDisconnected(client)).await;
+    }
+
+    /// Ask the router to stop Roslyn and end the session
+    pub async fn shutdown(&self) {
+        let _ = self.events.send(Event::Shutdown).await;
+    }
+}
+
+impl Proxy {
+    pub fn new(roslyn_writer: LspWriter, persistent: bool) -> Self {
+        let (roslyn, queue) = Outbox::new();
+        let queued = roslyn.queued.clone();
+        let mut proxy = Self::with_roslyn_outbox(roslyn, persistent);
+        proxy.roslyn_writer = Some(spawn_writer(roslyn_writer, queue, queued, "Roslyn".to_string()));
+        proxy.client_log = logger::client_entries();
+        proxy.configures_logger = true;
+        proxy
+    }
+
+    fn with_roslyn_outbox(roslyn: Outbox, persistent: bool) -> Self {
+        let (events, queue) = mpsc::channel(CHANNEL_CAPACITY);
+        Self {
+            persistent,
+            idle_timeout: None,
+            command_line: Map::new(),
+            roslyn,
+            roslyn_writer: None,
+            outboxes: HashMap::new(),
+            stalled_clients: Vec::new(),
+            max_client_backlog: MAX_CLIENT_BACKLOG,
+            client_writers: HashMap::new(),
+            events: queue,
+            client_log: mpsc::unbounded_channel().1,
+            log_backlog: VecDeque::new(),
+            configures_logger: false,
+            handle: ProxyHandle {
+                events,
+                next_client_id: Arc::new(AtomicU64::new(0)),
+            },
+            state: SessionState {
+                idle_since: Some(Instant::now()),
+                ..SessionState::default()
+            },
+        }
+    }
+
+    /// Stop Roslyn once no client has been attached for `timeout`
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Settings from `--set` options; `initializationOptions` still take precedence
+    pub fn with_command_line_config(mut self, settings: Map<String, Value>) -> Self {
+        self.command_line = settings;
+        self
+    }
+
+    /// Handle for connecting clients to this session
+    pub fn handle(&self) -> ProxyHandle {
+        self.handle.clone()
+    }
+
+    /// Route messages until the session ends, then flush whatever is still queued.
+    ///
+    /// The session ends when the client sends `exit` or goes away (standalone mode),
+    /// when Roslyn closes its side, on [`ProxyHandle::shutdown`] or when the idle
+    /// timeout expires. Returns whether it ended cleanly: Roslyn was told to shut down
+    /// before `exit`, rather than the client vanishing or Roslyn quitting on its own.
+    pub async fn run(mut self, roslyn_reader: LspReader) -> bool {
+        let reader = tokio::spawn(read_roslyn(roslyn_reader, self.handle.events.clone()));
+
+        let mut roslyn_open = true;
+        let clean = loop {
+            let idle_deadline = self
+                .idle_timeout
+                .zip(self.state.idle_since)
+                .map(|(timeout, since)| since + timeout);
+            let request_deadline = (self.state.requests.values().filter_map(|r| r.deadline))
+                .chain(self.state.held_requests.iter().map(|h| h.release_at))
+                .min();
+            let event = tokio::select! {
+                event = self.events.recv() => event,
+                _ = sleep_until(idle_deadline) => None,
+                _ = sleep_until(request_deadline) => Some(Event::RequestsDue),
+                Some(entry) = self.client_log.recv() => Some(Event::Log(entry)),
+            };
+            // The router holds a sender itself, so only the idle timer ends the wait empty-handed
+            let Some(event) = event else {
+                logger::info(format!(
+                    "[roslyn_wrapper] No clients for {}s; stopping Roslyn",
+                    self.idle_timeout.unwrap_or_default().as_secs()
+                ));
+                self.shutdown_roslyn().await;
+                break true;
+            };
+            roslyn_open &= !matches!(event, Event::RoslynClosed);
+            if let ControlFlow::Break(clean) = self.handle_event(event).await {
+                break clean;
+            }
+        };
+
+        if roslyn_open && !self.state.roslyn_closed {
+            // Keep reading until Roslyn closes its end, so its reply to `shutdown`
+            // doesn't hit a closed pipe
+            let drained = tokio::time::timeout(FLUSH_TIMEOUT, async {
+                while let Some(event) = self.events.recv().await {
+                    if matches!(event, Event::RoslynClosed) {
+                        break;
+                    }
+                    discard_while_stopping(event);
+                }
+            });
+            let _ = drained.await;
+        }
+        reader.abort();
+        self.close().await;
+        clean
+    }
+
+    /// Close every outbox and give the writer tasks a moment to drain them
+    async fn close(self) {
+        let Proxy {
+            roslyn,
+            roslyn_writer,
+            outboxes,
+            client_writers,
+            state,
+            ..
+        } = self;
+        if let Some(restore) = state.restore {
+            restore.task.abort();
+        }
+        drop(roslyn);
+        drop(outboxes);
+
+        let writers = roslyn_writer.into_iter().chain(client_writers.into_values());
+        let drained = tokio::time::timeout(FLUSH_TIMEOUT, async {
+            for writer in writers {
+                let _ = writer.await;
+            }
+        });
+        if drained.await.is_err() {
+            logger::error("[roslyn_wrapper] Timed out flushing queued messages");
+        }
+    }
+
+    /// Apply one event to the session; breaks with the clean-exit flag once it is over
+    async fn handle_event(&mut self, event: Event) -> ControlFlow<bool> {
+        self.apply_event(event).await?;
+        // Dropping a client can leave another one over its backlog in turn
+        while let Some(client) = self.stalled_clients.pop() {
+            self.client_gone(client).await?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    async fn apply_event(&mut self, event: Event) -> ControlFlow<bool> {
+        match event {
+            Event::ClientConnected { client, outbox, writer } => {
+                self.attach_client(client, outbox, writer);
+            }
+            Event::FromClient(client, _) if !self.state.clients.contains_key(&client) => {
+                // Left over from a client that already sent `exit`
+            }
+            Event::FromClient(client, Incoming::Malformed(reason)) => {
+                logger::error(format!("[roslyn_wrapper] Malformed message from client: {reason}"));
+                self.send_to_client(client, lsp::parse_error(&reason));
+            }
+            Event::FromClient(client, Incoming::Message(msg)) => {
+                logger::message(Direction::FromClient, &msg, self.persistent.then_some(client), None);
+                recording::record(Direction::FromClient, &msg, self.persistent.then_some(client));
+                let span = match (msg.method(), msg.id()) {
+                    (Some(method), Some(id)) => request_span(method, id),
+                    _ => Span::none(),
+                };
+                return self.client_message(client, msg).instrument(span).await;
+            }
+            Event::ClientDisconnected(client) => {
+                if !self.persistent {
+                    logger::error("[roslyn_wrapper] Client disconnected without exit; stopping Roslyn");
+                }
+                return self.client_gone(client).await;
+            }
+            Event::EditorExited(client, pid) if self.state.clients.contains_key(&client) => {
+                // A crashed editor may leave the wrapper's input open, so this can come first
+                logger::error(format!("[roslyn_wrapper] Editor process {pid} exited; dropping client {client}"));
+                return self.client_gone(client).await;
+            }
+            Event::EditorExited(..) => {}
+            Event::FromRoslyn(Incoming::Malformed(reason)) => {
+                logger::error(format!("[roslyn_wrapper] Malformed message from Roslyn: {reason}"));
+                self.send_to_roslyn(lsp::parse_error(&reason));
+            }
+            Event::FromRoslyn(Incoming::Message(msg)) => {
+                recording::record(Direction::FromRoslyn, &msg, None);
+                if msg.method().is_some() {
+                    // Responses are logged once routing knows the request they answer
+                    logger::message(Direction::FromRoslyn, &msg, None, None);
+                }
+                let span = match (msg.method(), msg.id()) {
+                    (None, Some(id)) => self.state.requests.get(&id_key(id)).map(|request| request.span.clone()),
+                    _ => None,
+                };
+                self.handle_roslyn_message(msg).instrument(span.unwrap_or_else(Span::none)).await;
+            }
+            Event::RoslynClosed => {
+                logger::error("[roslyn_wrapper] Roslyn exited unexpectedly");
+                return ControlFlow::Break(false);
+            }
+            Event::RequestsDue => {
+                self.expire_requests().await;
+                self.release_held_requests(false).await;
+            }
+            Event::Restore(update) => self.restore_update(update).await,
+            Event::Shutdown => {
+                self.shutdown_roslyn().await;
+                return ControlFlow::Break(true);
+            }
+            Event::Log(entry) => match self.primary_client() {
+                Some(client) => self.send_log(client, entry).await,
+                None => {
+                    if self.log_backlog.len() == logger::MAX_PENDING_CLIENT_ENTRIES {
+                        self.log_backlog.pop_front();
+                    }
+                    self.log_backlog.push_back(entry);
+                }
+            },
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Handle a message from a client and pass it on to Roslyn unless the wrapper answered it
+    async fn client_message(&mut self, client: ClientId, msg: Message) -> ControlFlow<bool> {
+        match self.handle_client_message(client, msg).await {
+            ClientFlow::Forward(msg) => {
+                self.send_to_roslyn(msg);
+            }
+            ClientFlow::ForwardAndEnd(msg) => {
+                self.send_to_roslyn(msg);
+                let clean = self.state.shutdown_requested;
+                if !clean {
+                    logger::error("[roslyn_wrapper] Client sent exit without shutdown");
+                }
+                return ControlFlow::Break(clean);
+            }
+            ClientFlow::Handled => {}
+            ClientFlow::Disconnect => self.detach_client(client).await,
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// A client left without `exit`. A standalone session ends with it, so Roslyn
+    /// never outlives its editor.
+    async fn client_gone(&mut self, client: ClientId) -> ControlFlow<bool> {
+        if !self.persistent {
+            self.shutdown_roslyn().await;
+            return ControlFlow::Break(false);
+        }
+        self.detach_client(client).await;
+        ControlFlow::Continue(())
+    }
+
+    /// Give up on requests Roslyn did not answer in time: cancel them in Roslyn and
+    /// answer the client with a cancellation error
+    async fn expire_requests(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .state
+            .requests
+            .iter()
+            .filter(|(_, request)| request.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in expired {
+            let Some(request) = self.state.requests.remove(&key) else {
+                continue;
+            };
+            let waited = now.duration_since(request.started).as_secs_f64();
+            request.span.in_scope(|| {
+                logger::error(format!(
+                    "[roslyn_wrapper] Roslyn did not answer {} within {waited:.1}s; cancelling it",
+                    request.method
+                ))
+            });
+
+            let cancel = json!({
+                "jsonrpc": "2.0",
+                "method": "$/cancelRequest",
+                "params": { "id": request.roslyn_id }
+            });
+            self.send_to_roslyn(cancel);
+
+            let message = format!("Roslyn did not answer {} within {waited:.1}s", request.method);
+            let error = match request.method.as_str() {
+                // Pull diagnostics can be cancelled by the server explicitly
+                "textDocument/diagnostic" | "workspace/diagnostic" => json!({
+                    "code": JSONRPC_SERVER_CANCELLED,
+                    "message": message,
+                    "data": { "retriggerRequest": false }
+                }),
+                _ => json!({ "code": JSONRPC_REQUEST_CANCELLED, "message": message }),
+            };
+            let response = json!({ "jsonrpc": "2.0", "id": request.client_id, "error": error });
+            self.send_to_client(request.client, response);
+        }
+    }
+
+    /// Ask Roslyn to shut down and exit on behalf of the client(s)
+    async fn shutdown_roslyn(&mut self) {
+        let shutdown = json!({ "jsonrpc": "2.0", "id": SHUTDOWN_ID, "method": "shutdown" });
+        if !self.send_to_roslyn(shutdown) {
+            return;
+        }
+        // The session is ending, so whatever else arrives meanwhile is turned away
+        let id = json!(SHUTDOWN_ID);
+        let answered = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+            while let Some(event) = self.events.recv().await {
+                match event {
+                    Event::FromRoslyn(Incoming::Message(msg)) if msg.method().is_none() && msg.id() == Some(&id) => {
+                        return true;
+                    }
+                    Event::RoslynClosed => {
+                        self.state.roslyn_closed = true;
+                        return false;
+                    }
+                    event => discard_while_stopping(event),
+                }
+            }
+            false
+        });
+        match answered.await {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(_) => logger::warn(format!(
+                "[roslyn_wrapper] Roslyn did not answer shutdown within {}s; sending exit anyway",
+                SHUTDOWN_TIMEOUT.as_secs()
+            )),
+        }
+        self.send_to_roslyn(json!({ "jsonrpc": "2.0", "method": "exit" }));
+    }
+
+    /// Queue a message for Roslyn
+    fn send_to_roslyn(&self, msg: impl Into<Message>) -> bool {
+        let msg = msg.into();
+        logger::message(Direction::ToRoslyn, &msg, None, None);
+        recording::record(Direction::ToRoslyn, &msg, None);
+        if !self.roslyn.push(msg) {
+            logger::error("[roslyn_wrapper] Error forwarding to Roslyn: connection closed");
+            return false;
+        }
+        true
+    }
+
+    /// Queue a message for one client.
+    ///
+    /// Returns `false` when that client is not attached. A closed outbox means its
+    /// writer failed; the client's reader notices the disconnect on its own. A client
+    /// whose backlog would pass `max_client_backlog` has stopped reading: its
+    /// connection is closed and it is dropped after the current event.
+    fn send_to_client(&mut self, client: ClientId, msg: impl Into<Message>) -> bool {
+        let Some(outbox) = self.outboxes.get(&client) else {
+            return false;
+        };
+        let msg = msg.into();
+        if outbox.backlog() + msg.body().len() > self.max_client_backlog {
+            logger::warn(format!(
+                "[roslyn_wrapper] Client {client} stopped reading ({} bytes queued); disconnecting it",
+                outbox.backlog()
+            ));
+            self.outboxes.remove(&client);
+            if let Some(writer) = self.client_writers.remove(&client) {
+                writer.abort();
+            }
+            self.stalled_clients.push(client);
+            return false;
+        }
+        logger::message(Direction::ToClient, &msg, self.persistent.then_some(client), None);
+        recording::record(Direction::ToClient, &msg, self.persistent.then_some(client));
+        if outbox.push(msg) {
+            return true;
+        }
+        self.outboxes.remove(&client);
+        false
+    }
+
+    /// The client that receives Roslyn's requests: the longest-connected one
+    fn primary_client(&self) -> Option<ClientId> {
+        self.outboxes.keys().min().copied()
+    }
+
+    async fn send_log(&mut self, client: ClientId, (message_type, message): logger::ClientEntry) {
+        let log = json!({
+            "jsonrpc": "2.0",
+            "method": "window/logMessage",
+            "params": { "type": message_type, "message": message }
+        });
+        self.send_to_client(client, log);
+    }
+
+    /// Hand a newly initialized client what was logged while nobody was attached
+    async fn send_log_backlog(&mut self, client: ClientId) {
+        for entry in std::mem::take(&mut self.log_backlog) {
+            self.send_log(client, entry).await;
+        }
+    }
+
+    /// Send a notification to every attached client
+    async fn broadcast(&mut self, msg: &Message) -> bool {
+        let clients: Vec<ClientId> = self.outboxes.keys().copied().collect();
+        let mut delivered = false;
+        for client in clients {
+            delivered |= self.send_to_client(client, msg.clone());
+        }
+        delivered
+    }
+
+    /// Register a new client connection
+    fn attach_client(&mut self, client: ClientId, outbox: Outbox, writer: JoinHandle<()>) {
+        let reattached = self.state.init_result.is_some();
+        self.state.clients.insert(
+            client,
+            ClientState {
+                reattached,
+                ..ClientState::default()
+            },
+        );
+        self.state.idle_since = None;
+        self.outboxes.insert(client, outbox);
+        self.client_writers.insert(client, writer);
+        if reattached {
+            logger::info(format!(
+                "[roslyn_wrapper] Client {client} attached to running Roslyn session"
+            ));
+        }
+    }
+
+    /// Drop a client and release everything it held in Roslyn
+    async fn detach_client(&mut self, client: ClientId) {
+        let Some(client_state) = self.state.clients.remove(&client) else {
+            return;
+        };
+        if let Some(watch) = &client_state.editor_watch {
+            watch.abort();
+        }
+        // Closing the outbox lets the writer finish and close the connection
+        self.outboxes.remove(&client);
+        self.client_writers.remove(&client);
+
+        let state = &mut self.state;
+        state.requests.retain(|_, request| request.client != client);
+        state.held_requests.retain(|held| held.client != client);
+        state.initialize_waiters.retain(|(owner, _)| *owner != client);
+
+        // Close documents nobody else has open
+        let mut closed = Vec::new();
+        state.document_owners.retain(|uri, owners| {
+            if owners.remove(&client) && owners.is_empty() {
+                state.document_versions.remove(uri);
+                closed.push(uri.clone());
+                return false;
+            }
+            true
+        });
+        if state.clients.is_empty() {
+            state.idle_since = Some(Instant::now());
+        }
+
+        for uri in &closed {
+            let close = json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didClose",
+                "params": { "textDocument": { "uri": uri } }
+            });
+            self.send_to_roslyn(close);
+        }
+        for id in &client_state.pending_server_requests {
+            self.fail_server_request(id).await;
+        }
+        for request in client_state.wrapper_requests.into_values() {
+            if let WrapperRequest::RestorePrompt(restore) = request {
+                // Nobody is left to answer the prompt; don't keep Roslyn waiting
+                self.answer_restore(restore.roslyn_id).await;
+            }
+        }
+
+        logger::info(format!(
+            "[roslyn_wrapper] Client {client} detached ({} documents closed, {} clients remaining)",
+            closed.len(),
+            self.state.clients.len()
+        ));
+    }
+
+    /// Answer a Roslyn request that cannot reach any client
+    async fn fail_server_request(&self, id_key: &str) {
+        let id: Value = serde_json::from_str(id_key).unwrap_or(Value::Null);
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {
+                "code": JSONRPC_REQUEST_FAILED,
+                "message": "No client connected to roslyn-wrapper"
+            }
+        });
+        self.send_to_roslyn(response);
+    }
+
+    /// Drop the client once the editor process that started it exits
+    fn watch_editor(&mut self, client: ClientId, pid: u32) {
+        if !lifecycle::process_alive(pid) {
+            // e.g. an editor in another PID namespace; nothing to watch from here
+            logger::info(format!("[roslyn_wrapper] Editor process {pid} is not visible; not watching it"));
+            return;
+        }
+        let Some(client_state) = self.state.clients.get_mut(&client) else {
+            return;
+        };
+        let events = self.handle.events.clone();
+        let watch = tokio::spawn(async move {
+            lifecycle::process_exited(pid).await;
+            let _ = events.send(Event::EditorExited(client, pid)).await;
+        });
+        if let Some(previous) = client_state.editor_watch.replace(watch) {
+            previous.abort();
+        }
+        logger::debug(format!("[roslyn_wrapper] Watching editor process {pid}"));
+    }
+
+    /// Send a request of the wrapper's own to a client
+    async fn send_wrapper_request(&mut self, client: ClientId, request: WrapperRequest, params: Value) {
+        self.state.next_request_id += 1;
+        let id = json!(format!("roslyn-wrapper/{}", self.state.next_request_id));
+        let method = request.method();
+        if let Some(client_state) = self.state.clients.get_mut(&client) {
+            client_state.wrapper_requests.insert(id_key(&id), request);
+        }
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        self.send_to_client(client, request);
+    }
+
+    async fn wrapper_request_answered(&mut self, client: ClientId, request: WrapperRequest, response: &Message) {
+        let response = response.to_value();
+        match request {
+            WrapperRequest::RegisterCapability => {}
+            WrapperRequest::CreateProgress(token) => {
+                if response.get("error").is_none() {
+                    self.show_progress(client, &token).await;
+                }
+            }
+            WrapperRequest::RestorePrompt(restore) => {
+                if response.pointer("/result/title").and_then(Value::as_str) == Some(RESTORE_ACTION) {
+                    self.queue_restore(restore).await;
+                } else {
+                    logger::info("[roslyn_wrapper] Restore declined");
+                    self.answer_restore(restore.roslyn_id).await;
+                }
+            }
+            WrapperRequest::FixAllScope { id, fix_all } => {
+                let scope = response.pointer("/result/title").and_then(Value::as_str);
+                match scope.filter(|scope| fix_all.flavors.iter().any(|f| f == scope)) {
+                    Some(scope) => {
+                        let params = commands::resolve_fix_all(&fix_all, scope);
+                        let command = Command::FixAll(fix_all);
+                        self.send_command_request(client, id, "codeAction/resolveFixAll", params, command).await;
+                    }
+                    None => self.respond(client, Some(&id), Value::Null).await,
+                }
+            }
+            WrapperRequest::ApplyEdit => {
+                if response.pointer("/result/applied").and_then(Value::as_bool) == Some(false) {
+                    let reason = response.pointer("/result/failureReason").and_then(Value::as_str);
+                    logger::info(format!(
+                        "[roslyn_wrapper] Editor did not apply the edit: {}",
+                        reason.unwrap_or("no reason given")
+                    ));
+                }
+            }
+            WrapperRequest::ShowDocument => {}
+        }
+    }
+
+    /// Answer a client request locally
+    async fn respond(&mut self, client: ClientId, id: Option<&Value>, result: Value) {
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": id.cloned().unwrap_or(Value::Null),
+            "result": result
+        });
+        self.send_to_client(client, response);
+    }
+
+    async fn handle_client_message(&mut self, client: ClientId, mut msg: Message) -> ClientFlow {
+        let Some(method) = msg.method().map(|s| s.to_string()) else {
+            // Response to a server request
+            if let Some(id_val) = msg.id() {
+                let key = id_key(id_val);
+                if let Some(client_state) = self.state.clients.get_mut(&client) {
+                    if let Some(request) = client_state.wrapper_requests.remove(&key) {
+                        self.wrapper_request_answered(client, request, &msg).await;
+                        return ClientFlow::Handled;
+                    }
+                    client_state.pending_server_requests.remove(&key);
+                }
+            }
+            return ClientFlow::Forward(msg);
+        };
+
+        let reattached = self.state.clients.get(&client).is_some_and(|c| c.reattached);
+        match method.as_str() {
+            "initialize" => {
+                if let Some(params) = msg.to_value().get("params") {
+                    capture_initialize_params(&mut self.state, &self.command_line, params, self.configures_logger);
+                    let editor_pid = params.get("processId").and_then(Value::as_u64);
+                    if let Some(pid) = editor_pid.and_then(|pid| u32::try_from(pid).ok()) {
+                        self.watch_editor(client, pid);
+                    }
+                    let window = params.pointer("/capabilities/window");
+                    let supports = |capability| {
+                        window.and_then(|w| w.pointer(capability)).and_then(Value::as_bool) == Some(true)
+                    };
+                    if let Some(client_state) = self.state.clients.get_mut(&client) {
+                        client_state.work_done_progress = supports("/workDoneProgress");
+                        client_state.show_document = supports("/showDocument/support");
+                    }
+                }
+                if let Some(result) = self.state.init_result.clone() {
+                    // Roslyn is already initialized; answer from the cached result
+                    self.respond(client, msg.id(), result).await;
+                    logger::debug("[roslyn_wrapper] Answered initialize from cached Roslyn session");
+                    self.send_log_backlog(client).await;
+                    return ClientFlow::Handled;
+                }
+                if self.state.initialize_in_flight {
+                    // Another client is initializing Roslyn; answer once it is done
+                    if let Some(client_state) = self.state.clients.get_mut(&client) {
+                        client_state.reattached = true;
+                    }
+                    let id = msg.id().cloned().unwrap_or(Value::Null);
+                    self.state.initialize_waiters.push((client, id));
+                    return ClientFlow::Handled;
+                }
+                self.state.initialize_in_flight = true;
+            }
+            "initialized" if reattached => {
+                // Roslyn already received `initialized`; replay dynamic registrations instead
+                if !self.state.registrations.is_empty() {
+                    let params = json!({ "registrations": self.state.registrations.clone() });
+                    self.send_wrapper_request(client, WrapperRequest::RegisterCapability, params).await;
+                }
+                return ClientFlow::Handled;
+            }
+            "workspace/executeCommand" => {
+                if let Some(command) = msg.to_value().get("params").and_then(Command::parse) {
+                    let id = msg.id().cloned().unwrap_or(Value::Null);
+                    self.run_command(client, id, command).await;
+                    return ClientFlow::Handled;
+                }
+            }
+            "shutdown" if self.persistent => {
+                // Keep Roslyn warm for the next client
+                self.respond(client, msg.id(), Value::Null).await;
+                return ClientFlow::Handled;
+            }
+            "exit" if self.persistent => {
+                return ClientFlow::Disconnect;
+            }
+            "shutdown" => self.state.shutdown_requested = true,
+            "exit" => return ClientFlow::ForwardAndEnd(msg),
+            // Only shared sessions need to know who has a document open
+            "textDocument/didOpen" if self.persistent => {
+                let value = msg.to_value();
+                if let Some(uri) = document_uri(&value) {
+                    let owners = self.state.document_owners.entry(uri.clone()).or_default();
+                    let already_open = !owners.is_empty();
+                    owners.insert(client);
+                    let Some(params) = value.get("params") else {
+                        return ClientFlow::Handled;
+                    };
+                    let document = &params["textDocument"];
+                    let version = shared_version(&mut self.state.document_versions, uri, &document["version"]);
+                    if already_open {
+                        // Roslyn already has the document; sync this client's text instead
+                        return ClientFlow::Forward(Message::from(json!({
+                            "jsonrpc": "2.0",
+                            "method": "textDocument/didChange",
+                            "params": {
+                                "textDocument": { "uri": document["uri"], "version": version },
+                                "contentChanges": [{ "text": document["text"] }]
+                            }
+                        })));
+                    }
+                }
+            }
+            "textDocument/didChange" if self.persistent => {
+                let mut value = msg.to_value();
+                let uri = document_uri(&value).filter(|uri| self.state.document_versions.contains_key(uri));
+                if let Some(uri) = uri {
+                    if let Some(document) = value.pointer_mut("/params/textDocument") {
+                        let version = shared_version(&mut self.state.document_versions, uri, &document["version"]);
+                        document["version"] = json!(version);
+                        return ClientFlow::Forward(Message::from(value));
+                    }
+                }
+            }
+            "textDocument/didClose" if self.persistent => {
+                if let Some(uri) = document_uri(&msg.to_value()) {
+                    if let Some(owners) = self.state.document_owners.get_mut(&uri) {
+                        owners.remove(&client);
+                        if !owners.is_empty() {
+                            // Still open in another client
+                            return ClientFlow::Handled;
+                        }
+                        self.state.document_owners.remove(&uri);
+                        self.state.document_versions.remove(&uri);
+                    }
+                }
+            }
+            "$/cancelRequest" if self.persistent || !self.state.held_requests.is_empty() => {
+                let mut value = msg.to_value();
+                let target = value.get("params").and_then(|p| p.get("id")).cloned();
+                if let Some(target) = target {
+                    let held = (self.state.held_requests.iter())
+                        .position(|held| held.client == client && held.msg.id() == Some(&target));
+                    if let Some(index) = held {
+                        // Roslyn never saw the request; answer it here
+                        self.state.held_requests.remove(index);
+                        let error = json!({ "code": JSONRPC_REQUEST_CANCELLED, "message": "Request cancelled" });
+                        let response = json!({ "jsonrpc": "2.0", "id": target, "error": error });
+                        self.send_to_client(client, response);
+                        return ClientFlow::Handled;
+                    }
+                    if self.persistent {
+                        // Point the cancellation at the rewritten request id
+                        let roslyn_id = self
+                            .state
+                            .requests
+                            .values()
+                            .find(|request| request.client == client && request.client_id == target)
+                            .map(|request| request.roslyn_id.clone());
+                        match roslyn_id {
+                            Some(id) => {
+                                value["params"]["id"] = id;
+                                msg = Message::from(value);
+                            }
+                            None => return ClientFlow::Handled,
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let loading = self.state.solution_load.is_some() && msg.id().is_some();
+        let hold = (self.state.hold_requests.as_ref()).filter(|hold| loading && hold.methods.contains(&method));
+        if let Some(hold) = hold {
+            logger::debug(format!("[roslyn_wrapper] Holding {method} until the solution has loaded"));
+            let now = Instant::now();
+            let release_at = now.checked_add(hold.max_wait).unwrap_or_else(|| {
+                logger::warn("[roslyn_wrapper] holdRequestsUntilLoaded.maxWait is out of range; using the default");
+                now + loading::DEFAULT_MAX_WAIT
+            });
+            self.state.held_requests.push(HeldRequest { client, method, msg, release_at });
+            return ClientFlow::Handled;
+        }
+
+        ClientFlow::Forward(self.track_request(client, method, msg))
+    }
+
+    /// Remember a client request about to go to Roslyn; in persistent mode it gets
+    /// an id that is unique across clients so the answer can be routed back
+    fn track_request(&mut self, client: ClientId, method: String, mut msg: Message) -> Message {
+        if let Some(client_id) = msg.id().cloned() {
+            let state = &mut self.state;
+            let mut roslyn_id = client_id.clone();
+            if self.persistent {
+                state.next_request_id += 1;
+                roslyn_id = json!(format!("c{client}-{}", state.next_request_id));
+                msg = msg.with_id(&roslyn_id);
+            }
+
+            let started = Instant::now();
+            // A timeout too long to represent as an instant is no timeout
+            let deadline = state.request_timeouts.for_method(&method).and_then(|t| started.checked_add(t));
+            // Held requests are sent later, outside the span they arrived in
+            let span = Some(Span::current())
+                .filter(|span| !span.is_none())
+                .unwrap_or_else(|| request_span(&method, &client_id));
+            state.requests.insert(
+                id_key(&roslyn_id),
+                InFlight { client, client_id, roslyn_id, method, started, deadline, command: None, span },
+            );
+        }
+        msg
+    }
+
+    /// Run a `roslyn.client.*` command the editor sent back through `workspace/executeCommand`
+    async fn run_command(&mut self, client: ClientId, id: Value, command: Result<Command, String>) {
+        let command = match command {
+            Ok(command) => command,
+            Err(message) => {
+                logger::error(format!("[roslyn_wrapper] {message}"));
+                let error = json!({ "code": JSONRPC_INVALID_PARAMS, "message": message });
+                self.send_to_client(client, json!({ "jsonrpc": "2.0", "id": id, "error": error }));
+                return;
+            }
+        };
+        logger::info(format!("[roslyn_wrapper] Running {}", command.name()));
+        match command {
+            Command::FixAll(fix_all) => match fix_all.flavors.as_slice() {
+                [] => self.respond(client, Some(&id), Value::Null).await,
+                [scope] => {
+                    let params = commands::resolve_fix_all(&fix_all, scope);
+                    let command = Command::FixAll(fix_all);
+                    self.send_command_request(client, id, "codeAction/resolveFixAll", params, command).await;
+                }
+                _ => {
+                    let params = commands::scope_prompt(&fix_all);
+                    self.send_wrapper_request(client, WrapperRequest::FixAllScope { id, fix_all }, params).await;
+                }
+            },
+            Command::PeekReferences { ref uri, ref position } => {
+                let params = commands::references(uri, position);
+                self.send_command_request(client, id, "textDocument/references", params, command).await;
+            }
+            Command::ComplexEdit { uri, edit, snippet } => {
+                let params = commands::apply_edit("Complete", commands::complex_edit(&uri, &edit, snippet));
+                self.send_wrapper_request(client, WrapperRequest::ApplyEdit, params).await;
+                self.respond(client, Some(&id), Value::Null).await;
+            }
+        }
+    }
+
+    /// Ask Roslyn for what a client command needs, as a request of that client
+    /// so it times out and can be cancelled like any other
+    async fn send_command_request(&mut self, client: ClientId, id: Value, method: &str, params: Value, command: Command) {
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let msg = self.track_request(client, method.to_string(), Message::from(request));
+        if let Some(request) = msg.id().and_then(|id| self.state.requests.get_mut(&id_key(id))) {
+            request.command = Some(command);
+        }
+        self.send_to_roslyn(msg);
+    }
+
+    /// Finish a client command with Roslyn's answer and reply to `workspace/executeCommand`
+    async fn command_answered(&mut self, client: ClientId, command: Command, msg: Message) {
+        let response = msg.to_value();
+        if response.get("error").is_some() {
+            return self.forward_response(client, msg).await;
+        }
+        let id = response.get("id");
+        match command {
+            Command::FixAll(fix_all) => {
+                match response.pointer("/result/edit") {
+                    Some(edit) => {
+                        let params = commands::apply_edit(&fix_all.title, edit.clone());
+                        self.send_wrapper_request(client, WrapperRequest::ApplyEdit, params).await;
+                    }
+                    None => logger::info(format!("[roslyn_wrapper] {} made no changes", fix_all.title)),
+                }
+                self.respond(client, id, Value::Null).await;
+            }
+            Command::PeekReferences { .. } => {
+                let locations = response.get("result").cloned().unwrap_or(Value::Null);
+                let show_document = self.state.clients.get(&client).is_some_and(|c| c.show_document);
+                let first = locations.get(0).and_then(commands::show_document);
+                if let Some(params) = first.filter(|_| show_document) {
+                    self.send_wrapper_request(client, WrapperRequest::ShowDocument, params).await;
+                }
+                self.respond(client, id, locations).await;
+            }
+            Command::ComplexEdit { .. } => self.forward_response(client, msg).await,
+        }
+    }
+
+    async fn handle_roslyn_message(&mut self, msg: Message) {
+        let Some(method) = msg.method().map(|s| s.to_string()) else {
+            return self.route_response(msg).await;
+        };
+
+        // Everything not matched here is forwarded byte for byte
+        let forward_msg = match custom::handler(&method) {
+            Some(Handler::Local(Local::Restore)) => return self.restore_requested(&msg).await,
+            Some(Handler::Local(Local::SolutionLoaded)) => {
+                self.finish_solution_load().await;
+                msg
+            }
+            Some(Handler::Translate(translate)) => Message::from(translate(msg.to_value())),
+            Some(Handler::Default(result)) => return self.answer_locally(&method, &msg, result()).await,
+            Some(Handler::Unsupported) => return self.reject_locally(&method, &msg),
+            None => self.normalize_standard(&method, msg).await,
+        };
+
+        if let Some(id_val) = forward_msg.id() {
+            // Server request: the primary client answers it
+            let key = id_key(id_val);
+            if let Some(client) = self.primary_client() {
+                if let Some(client_state) = self.state.clients.get_mut(&client) {
+                    // Remember it so it can be failed if the client goes away
+                    client_state.pending_server_requests.insert(key.clone());
+                }
+                if self.send_to_client(client, forward_msg) {
+                    return;
+                }
+                if let Some(client_state) = self.state.clients.get_mut(&client) {
+                    client_state.pending_server_requests.remove(&key);
+                }
+            }
+            logger::debug("[roslyn_wrapper] No client attached; failing Roslyn request");
+            self.fail_server_request(&key).await;
+            return;
+        }
+
+        // Notification: progress belongs to whoever created the token, the rest goes to everyone
+        let delivered = if method == "$/progress" {
+            match self.primary_client() {
+                Some(client) => self.send_to_client(client, forward_msg),
+                None => false,
+            }
+        } else {
+            self.broadcast(&forward_msg).await
+        };
+        if !delivered {
+            logger::debug("[roslyn_wrapper] No client attached; dropped message from Roslyn");
+        }
+    }
+
+    /// Standard LSP messages from Roslyn: fix what editors reject and note what the router tracks
+    async fn normalize_standard(&mut self, method: &str, msg: Message) -> Message {
+        match method {
+            // Normalize certain server->client requests with unit params
+            "workspace/inlayHint/refresh"
+            | "workspace/diagnostic/refresh"
+            | "workspace/codeLens/refresh" => {
+                let mut value = msg.to_value();
+                let needs_fix = match value.get("params") {
+                    None => false,
+                    Some(v) => !v.is_object(), // [] or null → {}
+                };
+                match value.as_object_mut() {
+                    Some(obj) if needs_fix => {
+                        obj.remove("params");
+                        logger::debug(format!(
+                            "[roslyn_wrapper] Removed params for unit method {method}"
+                        ));
+                        Message::from(value)
+                    }
+                    _ => msg,
+                }
+            }
+            "client/registerCapability" | "client/unregisterCapability" => {
+                self.track_registrations(method, &msg.to_value());
+                msg
+            }
+            "window/logMessage" if self.state.solution_load.is_some() => {
+                self.track_load_progress(&msg).await;
+                msg
+            }
+            _ => msg,
+        }
+    }
+
+    /// Answer a custom Roslyn request with `result` without involving the editor
+    async fn answer_locally(&mut self, method: &str, msg: &Message, result: Value) {
+        match msg.id() {
+            Some(id) => {
+                logger::debug(format!("[roslyn_wrapper] Answered {method} with a default result"));
+                self.send_to_roslyn(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+            }
+            None => logger::debug(format!("[roslyn_wrapper] Dropped {method}: no editor handles it")),
+        }
+    }
+
+    /// Answer a custom Roslyn request nobody implements with MethodNotFound, so Roslyn
+    /// doesn't take a made-up result for a real one
+    fn reject_locally(&mut self, method: &str, msg: &Message) {
+        match msg.id() {
+            Some(id) => {
+                logger::warn(format!("[roslyn_wrapper] Roslyn sent {method}, which the wrapper doesn't support"));
+                let error = json!({ "code": JSONRPC_METHOD_NOT_FOUND, "message": format!("Unhandled method {method}") });
+                self.send_to_roslyn(json!({ "jsonrpc": "2.0", "id": id, "error": error }));
+            }
+            None => logger::debug(format!("[roslyn_wrapper] Dropped {method}: no editor handles it")),
+        }
+    }
+
+    /// Route a Roslyn response back to the client that sent the request
+    async fn route_response(&mut self, msg: Message) {
+        let Some(id_val) = msg.id().cloned() else {
+            return;
+        };
+        let Some(request) = self.state.requests.remove(&id_key(&id_val)) else {
+            // Its client detached, the request timed out, or the wrapper sent it itself
+            logger::message(Direction::FromRoslyn, &msg, None, None);
+            logger::debug("[roslyn_wrapper] Dropped response to a request nobody is waiting for");
+            return;
+        };
+        let elapsed = request.started.elapsed();
+        let client = self.persistent.then_some(request.client);
+        logger::message(Direction::FromRoslyn, &msg, client, Some((&request.method, elapsed)));
+        if elapsed >= SLOW_REQUEST_THRESHOLD {
+            logger::warn(format!(
+                "[roslyn_wrapper] Slow request: {} took {}ms",
+                request.method,
+                elapsed.as_millis()
+            ));
+        }
+        let client = request.client;
+        let mut msg = if self.persistent {
+            msg.with_id(&request.client_id)
+        } else {
+            msg
+        };
+        if let Some(command) = request.command {
+            return self.command_answered(client, command, msg).await;
+        }
+
+        match request.method.as_str() {
+            "initialize" if !self.state.initialized => {
+                let mut value = msg.to_value();
+                let Some(result) = value.get_mut("result").filter(|r| r.get("capabilities").is_some()) else {
+                    return self.forward_response(client, msg).await;
+                };
+                // Editors only run commands a server advertises; the wrapper runs Roslyn's client commands
+                commands::advertise(&mut result["capabilities"]);
+                let result = result.clone();
+                msg = Message::from(value);
+                self.state.initialized = true;
+                self.state.initialize_in_flight = false;
+                self.state.init_result = Some(result.clone());
+                let waiters = std::mem::take(&mut self.state.initialize_waiters);
+                logger::info("[roslyn_wrapper] Initialization complete");
+
+                // Forward response to client first
+                self.forward_response(client, msg).await;
+                if self.configures_logger {
+                    logger::client_connected();
+                }
+                for (waiter, id) in waiters {
+                    self.respond(waiter, Some(&id), result.clone()).await;
+                }
+
+                // Then send solution/open notification
+                self.open_solution(client).await;
+                return;
+            }
+            // Editors can't run Roslyn's complex completion edits; turn them into plain edits
+            "textDocument/completion" | "completionItem/resolve" if msg.mentions(commands::COMPLEX_EDIT) => {
+                let mut value = msg.to_value();
+                let rewritten = value.get_mut("result").map_or(0, commands::inline_complex_edits);
+                if rewritten > 0 {
+                    logger::debug(format!("[roslyn_wrapper] Rewrote {rewritten} complex completion edits"));
+                    msg = Message::from(value);
+                }
+            }
+            // Normalize null results for known requests (e.g., textDocument/diagnostic)
+            "textDocument/diagnostic" if msg.result_is_null() => {
+                let mut value = msg.to_value();
+                if let Some(obj) = value.as_object_mut() {
+                    obj.insert(
+                        "result".to_string(),
+                        json!({
+                            "kind": "full",
+                            "items": []
+                        }),
+                    );
+                    logger::debug("[roslyn_wrapper] Normalized null diagnostic result to empty report");
+                }
+                msg = Message::from(value);
+            }
+            _ => {}
+        }
+
+        self.forward_response(client, msg).await;
+    }
+
+    async fn forward_response(&mut self, client: ClientId, msg: Message) {
+        self.send_to_client(client, msg);
+    }
+
+    /// Keep the set of dynamic registrations in sync so they can be replayed
+    fn track_registrations(&mut self, method: &str, msg: &Value) {
+        let params = msg.get("params");
+        match method {
+            "client/registerCapability" => {
+                if let Some(regs) = params
+                    .and_then(|p| p.get("registrations"))
+                    .and_then(|v| v.as_array())
+                {
+                    self.state.registrations.extend(regs.iter().cloned());
+                }
+            }
+            "client/unregisterCapability" => {
+                // The LSP spec really spells it "unregisterations"
+                if let Some(unregs) = params
+                    .and_then(|p| p.get("unregisterations"))
+                    .and_then(|v| v.as_array())
+                {
+                    let ids: HashSet<&str> = unregs
+                        .iter()
+                        .filter_map(|u| u.get("id").and_then(|v| v.as_str()))
+                        .collect();
+                    self.state.registrations.retain(|r| {
+                        r.get("id")
+                            .and_then(|v| v.as_str())
+                            .is_none_or(|id| !ids.contains(id))
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Track the load started by `solution/open` and offer the client a progress indicator
+    async fn start_solution_load(&mut self, client: ClientId, solution: String) {
+        let wants_progress = self.state.clients.get(&client).is_some_and(|c| c.work_done_progress);
+        self.state.solution_load = Some(SolutionLoad {
+            progress: LoadProgress::for_solution(&solution),
+            solution,
+            started: Instant::now(),
+            progress_client: wants_progress.then_some(client),
+            progress_shown: false,
+        });
+        if wants_progress {
+            let token = loading::PROGRESS_TOKEN.to_string();
+            let params = json!({ "token": token });
+            self.send_wrapper_request(client, WrapperRequest::CreateProgress(token), params).await;
+        }
+    }
+
+    /// The client accepted a progress token; start its indicator unless the work
+    /// finished in the meantime
+    async fn show_progress(&mut self, client: ClientId, token: &str) {
+        let begin = if token == loading::PROGRESS_TOKEN {
+            let load = self.state.solution_load.as_mut();
+            let Some(load) = load.filter(|load| load.progress_client == Some(client)) else {
+                return;
+            };
+            load.progress_shown = true;
+            loading::progress_begin(&load.solution, &load.progress)
+        } else {
+            let Some(running) = self.state.restore.as_mut() else {
+                return;
+            };
+            if running.progress.as_ref().is_none_or(|(c, t)| (*c, t.as_str()) != (client, token)) {
+                return;
+            }
+            running.progress_shown = true;
+            let projects = &running.request.projects;
+            let message = match projects.as_slice() {
+                [project] => loading::solution_name(project).to_string(),
+                _ => format!("{} projects", projects.len()),
+            };
+            let begin = json!({ "kind": "begin", "title": "Restoring packages", "message": message });
+            lsp::progress(token, begin)
+        };
+        self.send_to_client(client, begin);
+    }
+
+    /// Advance the load indicator from one of Roslyn's log lines
+    async fn track_load_progress(&mut self, log: &Message) {
+        let value = log.to_value();
+        let Some(line) = value.pointer("/params/message").and_then(Value::as_str) else {
+            return;
+        };
+        let Some(load) = self.state.solution_load.as_mut() else {
+            return;
+        };
+        if !load.progress.observe(line) {
+            return;
+        }
+        if let (true, Some(client)) = (load.progress_shown, load.progress_client) {
+            let report = loading::progress_report(&load.progress);
+            self.send_to_client(client, report);
+        }
+    }
+
+    /// Roslyn found projects with missing packages: restore them, or ask first
+    async fn restore_requested(&mut self, msg: &Message) {
+        let projects = restore::requested_projects(msg.to_value().get("params"));
+        let request = RestoreRequest { roslyn_id: msg.id().cloned(), projects };
+        if request.projects.is_empty() {
+            return self.answer_restore(request.roslyn_id).await;
+        }
+        logger::info(format!(
+            "[roslyn_wrapper] Roslyn reports {} projects need a restore",
+            request.projects.len()
+        ));
+        if !self.state.ask_before_restore {
+            return self.queue_restore(request).await;
+        }
+        let Some(client) = self.primary_client() else {
+            return self.answer_restore(request.roslyn_id).await;
+        };
+        let params = json!({
+            "type": LSP_MESSAGE_TYPE_INFO,
+            "message": "Some projects have unresolved dependencies. Run dotnet restore?",
+            "actions": [{ "title": RESTORE_ACTION }]
+        });
+        self.send_wrapper_request(client, WrapperRequest::RestorePrompt(request), params).await;
+    }
+
+    async fn queue_restore(&mut self, request: RestoreRequest) {
+        self.state.restore_queue.push_back(request);
+        self.start_next_restore().await;
+    }
+
+    /// Start the next queued restore unless one is already running
+    async fn start_next_restore(&mut self) {
+        if self.state.restore.is_some() {
+            return;
+        }
+        let Some(request) = self.state.restore_queue.pop_front() else {
+            return;
+        };
+        let task = tokio::spawn(restore::run(
+            request.projects.clone(),
+            self.handle.events.clone(),
+            Event::Restore,
+        ));
+        let client = self
+            .primary_client()
+            .filter(|client| self.state.clients.get(client).is_some_and(|c| c.work_done_progress));
+        let progress = client.map(|client| {
+            self.state.next_request_id += 1;
+            (client, format!("roslyn-wrapper/restore/{}", self.state.next_request_id))
+        });
+        self.state.restore = Some(RunningRestore {
+            request,
+            task,
+            progress: progress.clone(),
+            progress_shown: false,
+        });
+        if let Some((client, token)) = progress {
+            let params = json!({ "token": token });
+            self.send_wrapper_request(client, WrapperRequest::CreateProgress(token), params).await;
+        }
+    }
+
+    /// Pass `dotnet restore` output on to the log and the indicator; once it is done,
+    /// answer Roslyn and start the next restore
+    async fn restore_update(&mut self, update: restore::Update) {
+        let Some(running) = self.state.restore.as_ref() else {
+            return;
+        };
+        let indicator = running.progress.clone().filter(|_| running.progress_shown);
+        match update {
+            restore::Update::Output(line) => {
+                logger::info(format!("[roslyn_wrapper] dotnet restore: {line}"));
+                if let Some((client, token)) = indicator {
+                    let report = json!({ "kind": "report", "message": line });
+                    self.send_to_client(client, lsp::progress(&token, report));
+                }
+            }
+            restore::Update::Finished { failed } => {
+                let Some(running) = self.state.restore.take() else {
+                    return;
+                };
+                let summary = if failed.is_empty() {
+                    "Restore complete".to_string()
+                } else {
+                    let names: Vec<&str> = failed.iter().map(|p| loading::solution_name(p)).collect();
+                    format!("Restore failed for {}", names.join(", "))
+                };
+                logger::info(format!("[roslyn_wrapper] {summary}"));
+                if let Some((client, token)) = indicator {
+                    let end = json!({ "kind": "end", "message": summary });
+                    self.send_to_client(client, lsp::progress(&token, end));
+                }
+                if !failed.is_empty() {
+                    let warning = json!({
+                        "jsonrpc": "2.0",
+                        "method": "window/showMessage",
+                        "params": {
+                            "type": LSP_MESSAGE_TYPE_WARNING,
+                            "message": format!("{summary}. See the roslyn-wrapper log for the dotnet restore output.")
+                        }
+                    });
+                    self.broadcast(&Message::from(warning)).await;
+                }
+                self.answer_restore(running.request.roslyn_id).await;
+                self.start_next_restore().await;
+            }
+        }
+    }
+
+    async fn answer_restore(&mut self, roslyn_id: Option<Value>) {
+        if let Some(id) = roslyn_id {
+            self.send_to_roslyn(json!({ "jsonrpc": "2.0", "id": id, "result": null }));
+        }
+    }
+
+    /// Roslyn finished loading the projects: end the indicator and release held requests
+    async fn finish_solution_load(&mut self) {
+        let Some(load) = self.state.solution_load.take() else {
+            return;
+        };
+        let summary = loading::load_summary(&load.progress, load.started.elapsed());
+        logger::info(format!("[roslyn_wrapper] Project initialization complete: {summary}"));
+        if let (true, Some(client)) = (load.progress_shown, load.progress_client) {
+            self.send_to_client(client, loading::progress_end(&summary));
+        }
+        self.release_held_requests(true).await;
+    }
+
+    /// Forward held requests: all of them once the solution has loaded, otherwise
+    /// only those that have waited for the configured maximum
+    async fn release_held_requests(&mut self, loaded: bool) {
+        let now = Instant::now();
+        let (release, keep): (Vec<_>, Vec<_>) = std::mem::take(&mut self.state.held_requests)
+            .into_iter()
+            .partition(|held| loaded || held.release_at <= now);
+        self.state.held_requests = keep;
+
+        if loaded && !release.is_empty() {
+            logger::info(format!("[roslyn_wrapper] Forwarding {} requests held during solution load", release.len()));
+        }
+        for held in release {
+            if !loaded {
+                logger::error(format!(
+                    "[roslyn_wrapper] Solution still loading; forwarding held {} anyway",
+                    held.method
+                ));
+            }
+            let msg = self.track_request(held.client, held.method, held.msg);
+            self.send_to_roslyn(msg);
+        }
+    }
+
+    /// Send `solution/open` for the configured or discovered solution
+    async fn open_solution(&mut self, client: ClientId) {
+        let maybe_solution = if self.state.solution_uri.is_some() {
+            self.state.solution_uri.clone()
+        } else {
+            // attempt discovery from all workspace roots (rootUri and workspaceFolders)
+            self.state
+                .workspace_roots
+                .iter()
+                .find_map(|r| path_utils::try_find_solution_or_project(r))
+        };
+
+        if let Some(uri) = maybe_solution {
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "solution/open",
+                "params": {
+                    "solution": uri
+                }
+            });
+            logger::info("[roslyn_wrapper] Sending solution/open notification");
+            self.send_to_roslyn(notification);
+            self.start_solution_load(client, uri).await;
+        } else {
+            logger::info("[roslyn_wrapper] No solution or project found to open");
+            // Inform the client so users understand why features are limited
+            let info_msg = json!({
+                "jsonrpc": "2.0",
+                "method": "window/showMessage",
+                "params": {
+                    "type": LSP_MESSAGE_TYPE_WARNING,
+                    "message": "No .sln or .csproj found in the workspace. C# features are limited until a solution or project is opened. Open a folder with a .sln/.csproj or configure the 'solution' option in the C# extension."
+                }
+            });
+            if !self.send_to_client(client, info_msg) {
+                logger::error("[roslyn_wrapper] Failed to send no-solution warning");
+            }
+        }
+    }
+}
+
+/// Capture workspace roots from `initialize`, then the configuration with its
+/// `initializationOptions` on top. Logging is configured by the session's first client.
+fn capture_initialize_params(
+    state: &mut SessionState,
+    command_line: &Map<String, Value>,
+    params: &Value,
+    configure_logger: bool,
+) {
+    let roots = workspace_roots(params);
+    let workspace = roots.first().cloned().or_else(|| std::env::current_dir().ok());
+    let config = config::load(workspace.as_deref(), command_line, params.get("initializationOptions"));
+    config.log_effective();
+
+    if let Some(applied) = &state.settings {
+        // Roslyn, its workspace and the wrapper's session options are shared
+        if applied.session_differs(&config) {
+            logger::info("[roslyn_wrapper] Keeping the settings of the session's first client");
+        }
+        if config.solution.is_some() && config.solution != applied.solution {
+            logger::info(
+                "[roslyn_wrapper] Ignoring different solution from reconnecting client; Roslyn keeps the loaded one",
+            );
+        }
+        return;
+    }
+
+    if !roots.is_empty() {
+        state.workspace_roots = roots;
+        logger::info("[roslyn_wrapper] Captured workspace roots");
+    }
+    if configure_logger {
+        config.apply_logging();
+        logger::mirror_to_client(config.client_log_level.as_deref());
+    }
+    if let Some(timeouts) = &config.request_timeouts {
+        state.request_timeouts.configure(timeouts);
+    }
+    state.ask_before_restore = !config.auto_restore;
+    state.hold_requests = HoldRequests::from_option(&config.hold_requests_until_loaded);
+    if let Some(solution) = &config.solution {
+        state.solution_uri = Some(solution.clone());
+        logger::info("[roslyn_wrapper] Found solution URI");
+    }
+    state.settings = Some(config);
+}
+
+/// Workspace roots named in `initialize`: `rootUri`, or else the `workspaceFolders`
+fn workspace_roots(params: &Value) -> Vec<PathBuf> {
+    let root_uri = params.get("rootUri").and_then(Value::as_str);
+    if let Some(path) = root_uri.and_then(|uri| path_utils::url_to_path(uri).ok()) {
+        return vec![path];
+    }
+    let folders = params.get("workspaceFolders").and_then(Value::as_array);
+    (folders.into_iter().flatten())
+        .filter_map(|folder| folder.get("uri").and_then(Value::as_str))
+        .filter_map(|uri| path_utils::url_to_path(uri).ok())
+        .collect()
+}
+
+fn document_uri(msg: &Value) -> Option<String> {
+    msg.get("params")?
+        .get("textDocument")?
+        .get("uri")?
+        .as_str()
+        .map(|s| s.to_string())
+}
+
+/// Version to give Roslyn for a client's edit of a shared document: the client's own
+/// version unless another client has already taken Roslyn past it
+fn shared_version(versions: &mut HashMap<String, i64>, uri: String, requested: &Value) -> i64 {
+    let requested = requested.as_i64().unwrap_or(0);
+    let version = match versions.get(&uri) {
+        Some(&last) => requested.max(last + 1),
+        None => requested,
+    };
+    versions.insert(uri, version);
+    version
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::BufReader;
+
+    fn drain(queue: &mut mpsc::UnboundedReceiver<Message>) -> Vec<Value> {
+        let mut out = Vec::new();
+        while let Ok(msg) = queue.try_recv() {
+            out.push(msg.to_value());
+        }
+        out
+    }
+
+    /// Router whose Roslyn outbox can be inspected directly
+    fn router(persistent: bool) -> (Proxy, mpsc::UnboundedReceiver<Message>) {
+        let (roslyn, queue) = Outbox::new();
+        (Proxy::with_roslyn_outbox(roslyn, persistent), queue)
+    }
+
+    async fn connect(proxy: &mut Proxy) -> (ClientId, mpsc::UnboundedReceiver<Message>) {
+        let client = proxy.handle.next_client();
+        let (outbox, queue) = Outbox::new();
+        let writer = tokio::spawn(async {});
+        let flow = proxy
+            .handle_event(Event::ClientConnected { client, outbox, writer })
+            .await;
+        assert!(flow.is_continue());
+        (client, queue)
+    }
+
+    async fn from_client(proxy: &mut Proxy, client: ClientId, msg: Value) -> ControlFlow<bool> {
+        proxy
+            .handle_event(Event::FromClient(client, Incoming::Message(msg.into())))
+            .await
+    }
+
+    #[tokio::test]
+    async fn reconnecting_client_reuses_roslyn_session() {
+        let (mut proxy, mut roslyn) = router(true);
+        proxy.state.initialized = true;
+        proxy.state.solution_uri = Some("file:///work/App.sln".to_string());
+        proxy.state.init_result = Some(json!({ "capabilities": { "hoverProvider": true } }));
+        proxy.state.registrations = vec![json!({ "id": "1", "method": "workspace/didChangeWatchedFiles" })];
+
+        let (client, mut to_client) = connect(&mut proxy).await;
+        for msg in [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": { "textDocument": { "uri": "file:///work/A.cs" } } }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ] {
+            assert!(from_client(&mut proxy, client, msg).await.is_continue());
+        }
+
+        let to_client = drain(&mut to_client);
+        assert_eq!(to_client[0]["result"]["capabilities"]["hoverProvider"], true);
+        assert_eq!(to_client[1]["method"], "client/registerCapability");
+        assert_eq!(to_client[2]["id"], 2);
+        assert!(to_client[2]["result"].is_null());
+
+        let to_roslyn: Vec<String> = drain(&mut roslyn)
+            .iter()
+            .map(|m| m["method"].as_str().unwrap_or_default().to_string())
+            .collect();
+        assert_eq!(to_roslyn, ["textDocument/didOpen", "textDocument/didClose"]);
+        assert!(proxy.state.idle_since.is_some());
+    }
+
+    #[tokio::test]
+    async fn a_client_that_stops_reading_is_dropped_without_stalling_the_others() {
+        let (mut proxy, mut roslyn) = router(true);
+        proxy.state.init_result = Some(json!({ "capabilities": {} }));
+        proxy.max_client_backlog = 4096;
+        // Nothing ever drains the first client's outbox
+        let (stuck, _stuck_out) = connect(&mut proxy).await;
+        let (reading, mut reading_out) = connect(&mut proxy).await;
+
+        let hover = json!({ "jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": {} });
+        for client in [stuck, reading] {
+            assert!(from_client(&mut proxy, client, hover.clone()).await.is_continue());
+        }
+        let ids: Vec<Value> = drain(&mut roslyn).iter().map(|m| m["id"].clone()).collect();
+
+        let big = "x".repeat(8192);
+        let answer = json!({ "jsonrpc": "2.0", "id": ids[0], "result": { "contents": big } });
+        let flow = proxy.handle_event(Event::FromRoslyn(Incoming::Message(answer.into()))).await;
+        assert!(flow.is_continue());
+        assert!(!proxy.state.clients.contains_key(&stuck));
+
+        let answer = json!({ "jsonrpc": "2.0", "id": ids[1], "result": { "contents": "int" } });
+        let flow = proxy.handle_event(Event::FromRoslyn(Incoming::Message(answer.into()))).await;
+        assert!(flow.is_continue());
+        let to_reading = drain(&mut reading_out);
+        assert_eq!(to_reading[0]["id"], 1);
+        assert_eq!(to_reading[0]["result"]["contents"], "int");
+    }
+
+    #[tokio::test]
+    async fn concurrent_clients_share_documents_and_get_their_own_responses() {
+        let (mut proxy, mut roslyn) = router(true);
+        proxy.state.init_result = Some(json!({ "capabilities": {} }));
+        let (first, mut first_out) = connect(&mut proxy).await;
+        let (second, mut second_out) = connect(&mut proxy).await;
+
+        let open = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///work/A.cs", "version": 1, "text": "class A {}" } }
+        });
+        let hover = json!({ "jsonrpc": "2.0", "id": 7, "method": "textDocument/hover", "params": {} });
+        for client in [first, second] {
+            for msg in [open.clone(), hover.clone()] {
+                assert!(from_client(&mut proxy, client, msg).await.is_continue());
+            }
+        }
+
+        let to_roslyn = drain(&mut roslyn);
+        assert_eq!(to_roslyn[0]["method"], "textDocument/didOpen");
+        assert_eq!(to_roslyn[2]["method"], "textDocument/didChange");
+        assert_ne!(to_roslyn[1]["id"], to_roslyn[3]["id"]);
+
+        let second_hover_id = to_roslyn[3]["id"].clone();
+        let response = json!({ "jsonrpc": "2.0", "id": second_hover_id, "result": null });
+        let flow = proxy
+            .handle_event(Event::FromRoslyn(Incoming::Message(response.into())))
+            .await;
+        assert!(flow.is_continue());
+        assert!(drain(&mut first_out).is_empty());
+        assert_eq!(drain(&mut second_out)[0]["id"], 7);
+
+        assert!(proxy.handle_event(Event::ClientDisconnected(first)).await.is_continue());
+        assert!(drain(&mut roslyn).is_empty());
+        assert!(proxy.handle_event(Event::ClientDisconnected(second)).await.is_continue());
+        assert_eq!(drain(&mut roslyn)[0]["method"], "textDocument/didClose");
+        assert!(proxy.state.idle_since.is_some());
+    }
+
+    #[tokio::test]
+    async fn documents_opened_by_a_second_client_keep_roslyn_versions_increasing() {
+        let (mut proxy, mut roslyn) = router(true);
+        proxy.state.init_result = Some(json!({ "capabilities": {} }));
+        let (first, _first_out) = connect(&mut proxy).await;
+        let (second, _second_out) = connect(&mut proxy).await;
+        let document = |version: i64| json!({ "uri": "file:///work/A.cs", "version": version, "text": "class A {}" });
+
+        let open = json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": { "textDocument": document(1) } });
+        assert!(from_client(&mut proxy, first, open).await.is_continue());
+        let change = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": { "textDocument": document(5), "contentChanges": [{ "text": "class A { }" }] }
+        });
+        assert!(from_client(&mut proxy, first, change).await.is_continue());
+
+        let open = json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": { "textDocument": document(1) } });
+        assert!(from_client(&mut proxy, second, open).await.is_continue());
+        let change = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": { "textDocument": document(2), "contentChanges": [{ "text": "class A {  }" }] }
+        });
+        assert!(from_client(&mut proxy, second, change).await.is_continue());
+
+        let to_roslyn = drain(&mut roslyn);
+        let versions: Vec<_> = to_roslyn.iter().map(|m| m["params"]["textDocument"]["version"].clone()).collect();
+        assert_eq!(versions, [json!(1), json!(5), json!(6), json!(7)]);
+        assert_eq!(to_roslyn[2]["method"], "textDocument/didChange");
+        assert_eq!(to_roslyn[2]["params"]["contentChanges"][0]["text"], "class A {}");
+    }
+
+    #[tokio::test]
+    async fn roslyn_is_told_to_exit_after_it_answers_shutdown() {
+        let (mut proxy, mut roslyn) = router(true);
+        let events = proxy.handle.events.clone();
+        let stopping = tokio::spawn(async move {
+            proxy.shutdown_roslyn().await;
+            proxy
+        });
+
+        let shutdown = roslyn.recv().await.unwrap().to_value();
+        assert_eq!(shutdown["method"], "shutdown");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(roslyn.try_recv().is_err());
+
+        // An editor connecting now is told no by closing its connection
+        let late = 7;
+        let (outbox, mut late_out) = Outbox::new();
+        let connected = Event::ClientConnected { client: late, outbox, writer: tokio::spawn(async {}) };
+        events.send(connected).await.unwrap();
+
+        let response = json!({ "jsonrpc": "2.0", "id": shutdown["id"], "result": null });
+        events.send(Event::FromRoslyn(Incoming::Message(response.into()))).await.unwrap();
+        let proxy = stopping.await.unwrap();
+        assert_eq!(roslyn.try_recv().unwrap().to_value()["method"], "exit");
+        assert!(!proxy.state.roslyn_closed);
+        assert!(!proxy.state.clients.contains_key(&late));
+        assert!(late_out.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn unsupported_roslyn_requests_get_method_not_found() {
+        let (mut proxy, mut roslyn) = router(true);
+        let (_client, mut to_client) = connect(&mut proxy).await;
+        for (id, method) in [(1, "workspace/_roslyn_somethingNew"), (2, "razor/updateHtml")] {
+            let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": {} });
+            let flow = proxy.handle_event(Event::FromRoslyn(Incoming::Message(request.into()))).await;
+            assert!(flow.is_continue());
+        }
+
+        let answers = drain(&mut roslyn);
+        assert_eq!(answers[0]["id"], 1);
+        assert_eq!(answers[0]["error"]["code"], JSONRPC_METHOD_NOT_FOUND);
+        assert_eq!(answers[1]["id"], 2);
+        assert!(answers[1]["result"].is_null() && answers[1].get("error").is_none());
+        assert!(drain(&mut to_client).is_empty());
+    }
+
+    #[tokio::test]
+    async fn entries_logged_without_a_client_go_to_the_next_one() {
+        let (mut proxy, _roslyn) = router(true);
+        proxy.state.init_result = Some(json!({ "capabilities": {} }));
+        let entry = (LSP_MESSAGE_TYPE_WARNING, "[roslyn_wrapper] Restore failed".to_string());
+        assert!(proxy.handle_event(Event::Log(entry)).await.is_continue());
+
+        let (client, mut to_client) = connect(&mut proxy).await;
+        let initialize = json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} });
+        assert!(from_client(&mut proxy, client, initialize).await.is_continue());
+        let to_client = drain(&mut to_client);
+        assert_eq!(to_client[0]["id"], 1);
+        assert_eq!(to_client[1]["method"], "window/logMessage");
+        assert_eq!(to_client[1]["params"]["message"], "[roslyn_wrapper] Restore failed");
+        assert!(proxy.log_backlog.is_empty());
+    }
+
+    #[tokio::test]
+    async fn later_clients_keep_the_session_settings_of_the_first() {
+        let (mut proxy, _roslyn) = router(true);
+        proxy.state.init_result = Some(json!({ "capabilities": {} }));
+        let initialize = |root: &str, options: Value| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": { "rootUri": root, "initializationOptions": options }
+            })
+        };
+
+        let (first, _first_out) = connect(&mut proxy).await;
+        let options = json!({ "autoRestore": false, "requestTimeouts": { "textDocument/hover": 2 } });
+        let first_root = if cfg!(windows) { "file:///C:/work/first" } else { "file:///work/first" };
+        assert!(from_client(&mut proxy, first, initialize(first_root, options)).await.is_continue());
+        let roots = proxy.state.workspace_roots.clone();
+
+        let (second, _second_out) = connect(&mut proxy).await;
+        let options = json!({ "autoRestore": true, "requestTimeouts": { "default": 1 } });
+        let second_root = if cfg!(windows) { "file:///C:/work/second" } else { "file:///work/second" };
+        assert!(from_client(&mut proxy, second, initialize(second_root, options)).await.is_continue());
+
+        assert!(proxy.state.ask_before_restore);
+        assert_eq!(proxy.state.workspace_roots, roots);
+        let timeouts = &proxy.state.request_timeouts;
+        assert_eq!(timeouts.for_method("textDocument/hover"), Some(Duration::from_secs(2)));
+        assert_eq!(timeouts.for_method("textDocument/definition"), None);
+    }
+
+    #[tokio::test]
+    async fn startup_entries_reach_the_client_and_the_first_client_configures_logging() {
+        let (mut proxy, mut roslyn) = router(true);
+        proxy.client_log = logger::client_entries();
+        proxy.configures_logger = true;
+        // What startup does before any editor has connected
+        config::WrapperConfig::default().apply_logging();
+        logger::info("[roslyn_wrapper] Early startup entry");
+
+        let (first, mut first_out) = connect(&mut proxy).await;
+        let initialize = |level: &str| {
+            json!({
+                "jsonrpc": "2.0", "id": 1, "method": "initialize",
+                "params": { "initializationOptions": { "clientLogLevel": "info", "logLevel": level } }
+            })
+        };
+        assert!(from_client(&mut proxy, first, initialize("debug")).await.is_continue());
+        let id = drain(&mut roslyn)[0]["id"].clone();
+        let initialized = json!({ "jsonrpc": "2.0", "id": id, "result": { "capabilities": {} } });
+        assert!(proxy.handle_event(Event::FromRoslyn(Incoming::Message(initialized.into()))).await.is_continue());
+        drain(&mut first_out);
+
+        while let Ok(entry) = proxy.client_log.try_recv() {
+            assert!(proxy.handle_event(Event::Log(entry)).await.is_continue());
+        }
+        let mirrored: Vec<Value> = drain(&mut first_out)
+            .into_iter()
+            .filter(|msg| msg["method"] == "window/logMessage")
+            .map(|msg| msg["params"]["message"].clone())
+            .collect();
+        assert!(mirrored.contains(&json!("[roslyn_wrapper] Early startup entry")));
+
+        let (second, _second_out) = connect(&mut proxy).await;
+        assert!(from_client(&mut proxy, second, initialize("trace")).await.is_continue());
+        assert_eq!(proxy.state.settings.as_ref().unwrap().log_level, "debug");
+    }
+
+    #[tokio::test]
+    async fn exit_ends_the_session_cleanly_only_after_shutdown() {
+        let shutdown = json!({ "jsonrpc": "2.0", "id": 9, "method": "shutdown" });
+        let exit = json!({ "jsonrpc": "2.0", "method": "exit" });
+
+        let (mut proxy, mut roslyn) = router(false);
+        let (client, _to_client) = connect(&mut proxy).await;
+        assert!(from_client(&mut proxy, client, shutdown).await.is_continue());
+        assert_eq!(from_client(&mut proxy, client, exit.clone()).await, ControlFlow::Break(true));
+        let methods: Vec<Value> = drain(&mut roslyn).iter().map(|m| m["method"].clone()).collect();
+        assert_eq!(methods, ["shutdown", "exit"]);
+
+        let (mut proxy, _roslyn) = router(false);
+        let (client, _to_client) = connect(&mut proxy).await;
+        assert_eq!(from_client(&mut proxy, client, exit).await, ControlFlow::Break(false));
+    }
+
+    #[tokio::test]
+    async fn requests_are_held_until_the_solution_has_loaded() {
+        let (mut proxy, mut roslyn) = router(false);
+        let (client, mut to_client) = connect(&mut proxy).await;
+        let initialize = json!({
+            "jsonrpc": "2.0", "id": 1, "method": "initialize",
+            "params": {
+                "capabilities": { "window": { "workDoneProgress": true } },
+                "initializationOptions": { "solution": "file:///work/App.sln", "holdRequestsUntilLoaded": true }
+            }
+        });
+        assert!(from_client(&mut proxy, client, initialize).await.is_continue());
+        let initialized = json!({ "jsonrpc": "2.0", "id": 1, "result": { "capabilities": {} } });
+        assert!(proxy.handle_event(Event::FromRoslyn(Incoming::Message(initialized.into()))).await.is_continue());
+        let roslyn_methods: Vec<Value> = drain(&mut roslyn).iter().map(|m| m["method"].clone()).collect();
+        assert_eq!(roslyn_methods, ["initialize", "solution/open"]);
+
+        // The progress token is created first, and the indicator starts once it is accepted
+        let to_client_now = drain(&mut to_client);
+        let create = &to_client_now[1];
+        assert_eq!(create["method"], "window/workDoneProgress/create");
+        let accepted = json!({ "jsonrpc": "2.0", "id": create["id"], "result": null });
+        assert!(from_client(&mut proxy, client, accepted).await.is_continue());
+        let begin = drain(&mut to_client);
+        assert_eq!(begin[0]["params"]["value"]["kind"], "begin");
+        assert_eq!(begin[0]["params"]["value"]["message"], "App.sln");
+
+        for request in [
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/definition", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "textDocument/hover", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "textDocument/references", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "$/cancelRequest", "params": { "id": 4 } }),
+        ] {
+            assert!(from_client(&mut proxy, client, request).await.is_continue());
+        }
+        // Only the hover goes through; the cancelled held request is answered locally
+        let forwarded = drain(&mut roslyn);
+        assert_eq!(forwarded.len(), 1);
+        assert_eq!(forwarded[0]["id"], 3);
+        let cancelled = drain(&mut to_client);
+        assert_eq!((cancelled[0]["id"].clone(), cancelled[0]["error"]["code"].clone()), (json!(4), json!(-32800)));
+
+        // Roslyn's project-load log lines advance the indicator and are still forwarded
+        let log = json!({
+            "jsonrpc": "2.0", "method": "window/logMessage",
+            "params": { "type": 3, "message": "[LanguageServerProjectSystem] Loading /work/App/App.csproj..." }
+        });
+        assert!(proxy.handle_event(Event::FromRoslyn(Incoming::Message(log.into()))).await.is_continue());
+        let report = drain(&mut to_client);
+        assert_eq!(report[0]["params"]["value"]["message"], "0/1 projects · App");
+        assert_eq!(report[1]["method"], "window/logMessage");
+
+        let loaded = json!({ "jsonrpc": "2.0", "method": "workspace/projectInitializationComplete" });
+        assert!(proxy.handle_event(Event::FromRoslyn(Incoming::Message(loaded.into()))).await.is_continue());
+        let released = drain(&mut roslyn);
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0]["method"], "textDocument/definition");
+        let to_client_now = drain(&mut to_client);
+        assert_eq!(to_client_now[0]["params"]["value"]["kind"], "end");
+        assert!(to_client_now[0]["params"]["value"]["message"].as_str().unwrap().starts_with("Loaded in "));
+        assert_eq!(to_client_now[1]["method"], "workspace/projectInitializationComplete");
+    }
+
+    #[tokio::test]
+    async fn declined_restore_prompt_still_answers_roslyn() {
+        let (mut proxy, mut roslyn) = router(false);
+        proxy.state.ask_before_restore = true;
+        let (client, mut to_client) = connect(&mut proxy).await;
+
+        let needs_restore = json!({
+            "jsonrpc": "2.0", "id": 5, "method": "workspace/_roslyn_projectNeedsRestore",
+            "params": { "projectFilePaths": ["/work/App/App.csproj"] }
+        });
+        assert!(proxy.handle_event(Event::FromRoslyn(Incoming::Message(needs_restore.into()))).await.is_continue());
+        let prompt = drain(&mut to_client).remove(0);
+        assert_eq!(prompt["method"], "window/showMessageRequest");
+        assert_eq!(prompt["params"]["actions"][0]["title"], "Restore");
+
+        let dismissed = json!({ "jsonrpc": "2.0", "id": prompt["id"], "result": null });
+        assert!(from_client(&mut proxy, client, dismissed).await.is_continue());
+        assert_eq!(drain(&mut roslyn), [json!({ "jsonrpc": "2.0", "id": 5, "result": null })]);
+        assert!(proxy.state.restore.is_none());
+    }
+
+    #[tokio::test]
+    async fn custom_requests_without_an_editor_handler_get_a_default_answer() {
+        let (mut proxy, mut roslyn) = router(false);
+        let (_client, mut to_client) = connect(&mut proxy).await;
+
+        let razor = json!({
+            "jsonrpc": "2.0", "id": 9, "method": "razor/provideDynamicFileInfo",
+            "params": { "razorDocument": { "uri": "file:///work/App/Index.razor" } }
+        });
+        assert!(proxy.handle_event(Event::FromRoslyn(Incoming::Message(razor.into()))).await.is_continue());
+        assert_eq!(drain(&mut roslyn), [json!({ "jsonrpc": "2.0", "id": 9, "result": null })]);
+
+        let toast = json!({
+            "jsonrpc": "2.0", "method": "window/_roslyn_showToast",
+            "params": { "message": "Hello", "messageType": 1 }
+        });
+        assert!(proxy.handle_event(Event::FromRoslyn(Incoming::Message(toast.into()))).await.is_continue());
+        let shown = drain(&mut to_client);
+        assert_eq!(shown.len(), 1);
+        assert_eq!(shown[0]["method"], "window/showMessage");
+        assert_eq!(shown[0]["params"]["type"], LSP_MESSAGE_TYPE_WARNING);
+    }
+
+    #[tokio::test]
+    async fn fix_all_command_asks_for_a_scope_and_applies_roslyns_edit() {
+        let (mut proxy, mut roslyn) = router(true);
+        let (client, mut to_client) = connect(&mut proxy).await;
+
+        let data = json!({ "UniqueIdentifier": "Use var", "FixAllFlavors": ["Document", "Solution"] });
+        let execute = json!({
+            "jsonrpc": "2.0", "id": 4, "method": "workspace/executeCommand",
+            "params": { "command": commands::FIX_ALL, "arguments": [data] }
+        });
+        assert!(from_client(&mut proxy, client, execute).await.is_continue());
+        assert!(drain(&mut roslyn).is_empty());
+        let prompt = drain(&mut to_client).remove(0);
+        assert_eq!(prompt["method"], "window/showMessageRequest");
+
+        let chosen = json!({ "jsonrpc": "2.0", "id": prompt["id"], "result": { "title": "Solution" } });
+        assert!(from_client(&mut proxy, client, chosen).await.is_continue());
+        let resolve = drain(&mut roslyn).remove(0);
+        assert_eq!(resolve["method"], "codeAction/resolveFixAll");
+        assert_eq!(resolve["params"]["scope"], "Solution");
+        assert_eq!(resolve["params"]["data"], data);
+
+        let edit = json!({ "changes": { "file:///work/A.cs": [] } });
+        let resolved = json!({ "jsonrpc": "2.0", "id": resolve["id"], "result": { "title": "Use var", "edit": edit } });
+        assert!(proxy.handle_event(Event::FromRoslyn(Incoming::Message(resolved.into()))).await.is_continue());
+        let to_client = drain(&mut to_client);
+        assert_eq!(to_client[0]["method"], "workspace/applyEdit");
+        assert_eq!(to_client[0]["params"], json!({ "label": "Use var", "edit": edit }));
+        assert_eq!(to_client[1], json!({ "jsonrpc": "2.0", "id": 4, "result": null }));
+    }
+
+    #[tokio::test]
+    async fn failed_restore_is_reported_and_answers_roslyn() {
+        let (mut proxy, mut roslyn) = router(false);
+        let (_client, mut to_client) = connect(&mut proxy).await;
+
+        let needs_restore = json!({
+            "jsonrpc": "2.0", "id": 5, "method": "workspace/_roslyn_projectNeedsRestore",
+            "params": { "projectFilePaths": ["/nonexistent/App/App.csproj"] }
+        });
+        assert!(proxy.handle_event(Event::FromRoslyn(Incoming::Message(needs_restore.into()))).await.is_continue());
+        assert!(proxy.state.restore.is_some());
+
+        // Fails whether or not dotnet is installed
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(60), proxy.events.recv())
+                .await
+                .expect("restore did not finish")
+                .unwrap();
+            let finished = matches!(&event, Event::Restore(restore::Update::Finished { failed }) if failed.len() == 1);
+            assert!(proxy.handle_event(event).await.is_continue());
+            if finished {
+                break;
+            }
+        }
+        assert_eq!(drain(&mut roslyn), [json!({ "jsonrpc": "2.0", "id": 5, "result": null })]);
+        let warning = drain(&mut to_client).remove(0);
+        assert_eq!(warning["method"], "window/showMessage");
+        assert!(warning["params"]["message"].as_str().unwrap().starts_with("Restore failed for App.csproj"));
+        assert!(proxy.state.restore.is_none());
+    }
+
+    #[tokio::test]
+    async fn unanswered_requests_are_cancelled_when_they_time_out() {
+        let (mut proxy, mut roslyn) = router(true);
+        proxy.state.request_timeouts.configure(&json!({ "default": 0.01, "textDocument/hover": 0.01 }));
+        let (client, mut to_client) = connect(&mut proxy).await;
+        for (id, method) in [(3, "textDocument/hover"), (4, "textDocument/diagnostic"), (5, "initialize")] {
+            let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": {} });
+            assert!(from_client(&mut proxy, client, request).await.is_continue());
+        }
+        let forwarded = drain(&mut roslyn);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(proxy.handle_event(Event::RequestsDue).await.is_continue());
+
+        // Roslyn is told to stop working on the rewritten ids...
+        let mut cancelled: Vec<Value> = drain(&mut roslyn)
+            .iter()
+            .map(|m| {
+                assert_eq!(m["method"], "$/cancelRequest");
+                m["params"]["id"].clone()
+            })
+            .collect();
+        cancelled.sort_by_key(|id| id.to_string());
+        assert_eq!(cancelled, [forwarded[0]["id"].clone(), forwarded[1]["id"].clone()]);
+
+        // ...and the client gets an error under its own ids; `initialize` never times out
+        let mut errors: Vec<(Value, Value)> = drain(&mut to_client)
+            .iter()
+            .map(|m| (m["id"].clone(), m["error"]["code"].clone()))
+            .collect();
+        errors.sort_by_key(|(id, _)| id.to_string());
+        assert_eq!(errors, [(json!(3), json!(-32800)), (json!(4), json!(-32802))]);
+
+        // A late answer is dropped
+        let late = json!({ "jsonrpc": "2.0", "id": forwarded[0]["id"], "result": null });
+        assert!(proxy.handle_event(Event::FromRoslyn(Incoming::Message(late.into()))).await.is_continue());
+        assert!(drain(&mut to_client).is_empty());
+        assert_eq!(proxy.state.requests.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn session_ends_when_the_editor_process_exits() {
+        let mut editor = tokio::process::Command::new("sleep").arg("0.2").spawn().unwrap();
+        let pid = editor.id().unwrap();
+        tokio::spawn(async move { editor.wait().await });
+
+        let (mut proxy, mut roslyn) = router(false);
+        let (client, _to_client) = connect(&mut proxy).await;
+        let initialize = json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "processId": pid } });
+        assert!(from_client(&mut proxy, client, initialize).await.is_continue());
+
+        // The editor's pipes may stay open; the watcher notices the process instead
+        let event = tokio::time::timeout(Duration::from_secs(5), proxy.events.recv())
+            .await
+            .expect("editor exit not noticed")
+            .unwrap();
+        assert!(matches!(event, Event::EditorExited(c, p) if c == client && p == pid));
+        let reply = json!({ "jsonrpc": "2.0", "id": SHUTDOWN_ID, "result": null });
+        let reply = Event::FromRoslyn(Incoming::Message(reply.into()));
+        proxy.handle.events.send(reply).await.unwrap();
+        assert_eq!(proxy.handle_event(event).await, ControlFlow::Break(false));
+        let methods: Vec<Value> = drain(&mut roslyn).iter().map(|m| m["method"].clone()).collect();
+        assert_eq!(methods, ["initialize", "shutdown", "exit"]);
+    }
+
+    #[tokio::test]
+    async fn standalone_session_stops_roslyn_when_the_client_leaves() {
+        let (client_end, proxy_client_end) = tokio::io::duplex(64 * 1024);
+        let (roslyn_end, proxy_roslyn_end) = tokio::io::duplex(64 * 1024);
+        let (client_read, client_write) = tokio::io::split(proxy_client_end);
+        let (roslyn_read, roslyn_write) = tokio::io::split(proxy_roslyn_end);
+
+        let proxy = Proxy::new(Box::new(roslyn_write), false);
+        let handle = proxy.handle();
+        tokio::spawn(async move {
+            handle
+                .serve_client(Box::new(BufReader::new(client_read)), Box::new(client_write))
+                .await
+        });
+        let session = tokio::spawn(proxy.run(Box::new(BufReader::new(roslyn_read))));
+
+        let (client_read, mut client_write) = tokio::io::split(client_end);
+        let mut client_read = BufReader::new(client_read);
+        let (roslyn_read, mut roslyn_write) = tokio::io::split(roslyn_end);
+        let mut roslyn_read = BufReader::new(roslyn_read);
+
+        let hover = json!({ "jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": {} });
+        write_message(&mut client_write, &Message::from(hover.clone())).await.unwrap();
+        let forwarded = read_lsp_message(&mut roslyn_read).await.unwrap();
+        assert_eq!(forwarded, Some(Incoming::Message(hover.into())));
+
+        let answer = json!({ "jsonrpc": "2.0", "id": 1, "result": { "contents": "int" } });
+        write_message(&mut roslyn_write, &Message::from(answer.clone())).await.unwrap();
+        let routed = read_lsp_message(&mut client_read).await.unwrap();
+        assert_eq!(routed, Some(Incoming::Message(answer.into())));
+
+        // The client hanging up stops Roslyn: `exit` follows its answer to `shutdown`
+        client_write.shutdown().await.unwrap();
+        let Some(Incoming::Message(msg)) = read_lsp_message(&mut roslyn_read).await.unwrap() else {
+            panic!("expected shutdown");
+        };
+        assert_eq!(msg.method(), Some("shutdown"));
+        let reply = json!({ "jsonrpc": "2.0", "id": SHUTDOWN_ID, "result": null });
+        write_message(&mut roslyn_write, &Message::from(reply)).await.unwrap();
+        let Some(Incoming::Message(msg)) = read_lsp_message(&mut roslyn_read).await.unwrap() else {
+            panic!("expected exit");
+        };
+        assert_eq!(msg.method(), Some("exit"));
+        roslyn_write.shutdown().await.unwrap();
+
+        // ...which ends the session and closes Roslyn's input
+        let clean = tokio::time::timeout(Duration::from_secs(2), session)
+            .await
+            .expect("session did not end")
+            .unwrap();
+        assert!(!clean);
+        assert_eq!(read_lsp_message(&mut roslyn_read).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,379 @@
+// Record-and-replay of LSP sessions.
+//
+// `--record <file>` writes every message the router sees to a session file, one JSON
+// object per line with the hop it took, the client and the milliseconds since the
+// recording started. `roslyn-wrapper replay <file>` plays the recorded client side
+// against a fresh server (this wrapper, or any command after `--`, such as a mock),
+// answers the server's requests the way the editor did, and diffs the responses to
+// the client's requests against the recording.
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{ExitCode, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::BufReader;
+use tokio::sync::mpsc;
+
+use crate::logger::Direction;
+use crate::lsp::{self, Incoming, Message};
+
+static RECORDER: Lazy<Mutex<Option<Recorder>>> = Lazy::new(|| Mutex::new(None));
+
+// How long a replay waits for outstanding responses once the script has run out
+const DEFAULT_REPLAY_TIMEOUT_SECS: u64 = 30;
+
+// Differences listed per response before the rest are summarized
+const MAX_DIFFERENCES_SHOWN: usize = 10;
+
+struct Recorder {
+    file: File,
+    started: Instant,
+}
+
+/// Start writing every message to `path`, replacing an earlier recording
+pub fn start(path: &Path) -> io::Result<()> {
+    let file = File::create(path)?;
+    *RECORDER.lock().unwrap() = Some(Recorder { file, started: Instant::now() });
+    Ok(())
+}
+
+/// Append one message to the recording, if one is running
+pub fn record(direction: Direction, msg: &Message, client: Option<u64>) {
+    let Ok(mut recorder) = RECORDER.lock() else {
+        return;
+    };
+    let Some(recorder) = recorder.as_mut() else {
+        return;
+    };
+    let line = entry_line(direction, msg, client, recorder.started.elapsed());
+    // Unbuffered, so the recording survives a crash of the session it reproduces
+    let _ = recorder.file.write_all(line.as_bytes());
+}
+
+fn entry_line(direction: Direction, msg: &Message, client: Option<u64>, elapsed: Duration) -> String {
+    // Bodies are kept as sent unless they span lines, which would break the format
+    let body = match msg.body().contains(&b'\n') {
+        true => msg.to_value().to_string(),
+        false => String::from_utf8_lossy(msg.body()).into_owned(),
+    };
+    let client = client.map(|c| format!(",\"client\":{c}")).unwrap_or_default();
+    format!(
+        "{{\"ms\":{},\"direction\":\"{}\"{client},\"message\":{body}}}\n",
+        elapsed.as_millis(),
+        direction.name()
+    )
+}
+
+/// One line of a session file
+#[derive(Deserialize)]
+struct Entry {
+    ms: u64,
+    direction: String,
+    #[serde(default)]
+    client: Option<u64>,
+    message: Value,
+}
+
+/// The client side of a recorded session, ready to be played back
+#[derive(Debug, Default)]
+struct Session {
+    /// Requests and notifications the client sent, with when it sent them and how
+    /// many of the `expected` responses it had received by then
+    script: Vec<(u64, usize, Value)>,
+    /// The client's answers to server requests, by method, in the order it gave them
+    answers: HashMap<String, VecDeque<Value>>,
+    /// Recorded responses to the client's requests: id, method and response
+    expected: Vec<(Value, String, Value)>,
+}
+
+impl Session {
+    /// Split a session file into what to send, how to answer and what to expect.
+    /// Only the first client is played back when several shared the session.
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        for (n, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let entry: Entry =
+                serde_json::from_str(line).map_err(|e| format!("line {}: {e}", n + 1))?;
+            entries.push(entry);
+        }
+        let from_client = Direction::FromClient.name();
+        let to_client = Direction::ToClient.name();
+        let client = entries.iter().find(|e| e.direction == from_client).and_then(|e| e.client);
+
+        let mut session = Session::default();
+        // Methods of the requests each side sent, by id
+        let mut client_requests = HashMap::new();
+        let mut server_requests = HashMap::new();
+        for entry in entries.into_iter().filter(|e| e.client == client) {
+            let msg = entry.message;
+            let method = msg.get("method").and_then(Value::as_str).map(String::from);
+            let id = msg.get("id").cloned();
+            match (entry.direction.as_str(), method, id) {
+                (d, Some(method), id) if d == from_client => {
+                    if let Some(id) = id {
+                        client_requests.insert(id.to_string(), method);
+                    }
+                    session.script.push((entry.ms, session.expected.len(), msg));
+                }
+                (d, None, Some(id)) if d == from_client => {
+                    if let Some(method) = server_requests.remove(&id.to_string()) {
+                        session.answers.entry(method).or_default().push_back(msg);
+                    }
+                }
+                (d, Some(method), Some(id)) if d == to_client => {
+                    server_requests.insert(id.to_string(), method);
+                }
+                (d, None, Some(id)) if d == to_client => {
+                    if let Some(method) = client_requests.remove(&id.to_string()) {
+                        session.expected.push((id, method, msg));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(session)
+    }
+}
+
+/// `roslyn-wrapper replay <file> [--timeout SECS] [-- <server command>...]`
+pub async fn replay(args: &[String]) -> io::Result<ExitCode> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let (options, server) = match args.iter().position(|a| a == "--") {
+        Some(pos) => (&args[..pos], &args[pos + 1..]),
+        None => (args, &[][..]),
+    };
+    let mut file = None;
+    let mut timeout = Duration::from_secs(DEFAULT_REPLAY_TIMEOUT_SECS);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--timeout" => {
+                let value = options.next().cloned().unwrap_or_default();
+                let secs = value
+                    .parse()
+                    .map_err(|_| invalid(format!("Invalid replay timeout: {value} (expected seconds)")))?;
+                timeout = Duration::from_secs(secs);
+            }
+            path if file.is_none() => file = Some(path.to_string()),
+            other => return Err(invalid(format!("Unexpected replay argument: {other}"))),
+        }
+    }
+    let file = file.ok_or_else(|| invalid("Usage: roslyn-wrapper replay <file> [--timeout SECS] [-- <server command>...]".to_string()))?;
+    let session = Session::parse(&std::fs::read_to_string(&file)?).map_err(|e| invalid(format!("{file}: {e}")))?;
+
+    // Without a command the session is played against this wrapper and a fresh Roslyn
+    let (program, server_args) = match server.split_first() {
+        Some((program, rest)) => (PathBuf::from(program), rest.to_vec()),
+        None => (std::env::current_exe()?, Vec::new()),
+    };
+    let mut child = tokio::process::Command::new(&program)
+        .args(&server_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut writer = child.stdin.take().ok_or_else(|| io::Error::other("Failed to get server stdin"))?;
+    let stdout = child.stdout.take().ok_or_else(|| io::Error::other("Failed to get server stdout"))?;
+
+    let (tx, mut rx) = mpsc::channel(64);
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(stdout);
+        while let Ok(Some(incoming)) = lsp::read_lsp_message(&mut reader).await {
+            if let Incoming::Message(msg) = incoming {
+                if tx.send(msg).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    println!(
+        "Replaying {} messages from {file} against {}",
+        session.script.len(),
+        program.display()
+    );
+    let Session { script, mut answers, expected } = session;
+    let mut actual: HashMap<String, Value> = HashMap::new();
+    let mut script = script.into_iter().peekable();
+    let started = Instant::now();
+    let mut finished: Option<Instant> = None;
+    loop {
+        // A message also waits for the responses the client had when it was recorded,
+        // so `exit` doesn't overtake the answer to `shutdown`; but only up to the timeout
+        let due = script.peek().map(|(ms, after, _)| {
+            let at = started + Duration::from_millis(*ms);
+            match answered(&expected[..*after], &actual) {
+                true => at,
+                false => at + timeout,
+            }
+        });
+        let give_up = finished.map(|at| at + timeout);
+        tokio::select! {
+            _ = tokio::time::sleep_until(due.unwrap_or(started).into()), if due.is_some() => {
+                let (_, _, msg) = script.next().expect("a message is due");
+                if lsp::write_message(&mut writer, &Message::from(msg)).await.is_err() {
+                    break;
+                }
+                if script.peek().is_none() {
+                    finished = Some(Instant::now());
+                }
+            }
+            msg = rx.recv() => {
+                let Some(msg) = msg else {
+                    break;
+                };
+                match (msg.method(), msg.id()) {
+                    // Answer the server's request the way the editor did
+                    (Some(method), Some(id)) => {
+                        let answer = answers
+                            .get_mut(method)
+                            .and_then(VecDeque::pop_front)
+                            .unwrap_or_else(|| json!({ "jsonrpc": "2.0", "id": null, "result": null }));
+                        let answer = Message::from(answer).with_id(id);
+                        if lsp::write_message(&mut writer, &answer).await.is_err() {
+                            break;
+                        }
+                    }
+                    (None, Some(id)) => {
+                        actual.insert(id.to_string(), msg.to_value());
+                    }
+                    _ => {}
+                }
+                if finished.is_some() && answered(&expected, &actual) {
+                    break;
+                }
+            }
+            _ = tokio::time::sleep_until(give_up.unwrap_or(started).into()), if give_up.is_some() => {
+                break;
+            }
+        }
+    }
+    drop(writer);
+    let _ = tokio::time::timeout(Duration::from_secs(5), child.wait()).await;
+
+    let mut mismatched = 0;
+    for (id, method, recorded) in &expected {
+        let Some(replayed) = actual.get(&id.to_string()) else {
+            mismatched += 1;
+            println!("MISSING {method} #{id}: no response");
+            continue;
+        };
+        let mut differences = Vec::new();
+        diff_values("", &outcome(recorded), &outcome(replayed), &mut differences);
+        if differences.is_empty() {
+            continue;
+        }
+        mismatched += 1;
+        println!("DIFFERS {method} #{id}:");
+        for difference in differences.iter().take(MAX_DIFFERENCES_SHOWN) {
+            println!("  {difference}");
+        }
+        if differences.len() > MAX_DIFFERENCES_SHOWN {
+            println!("  ... and {} more", differences.len() - MAX_DIFFERENCES_SHOWN);
+        }
+    }
+    println!(
+        "{} of {} responses matched the recording",
+        expected.len() - mismatched,
+        expected.len()
+    );
+    Ok(if mismatched == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+fn answered(expected: &[(Value, String, Value)], actual: &HashMap<String, Value>) -> bool {
+    expected.iter().all(|(id, _, _)| actual.contains_key(&id.to_string()))
+}
+
+/// The part of a response that is compared: its `result` or `error`
+fn outcome(response: &Value) -> Value {
+    let mut outcome = response.clone();
+    if let Some(fields) = outcome.as_object_mut() {
+        fields.remove("id");
+        fields.remove("jsonrpc");
+    }
+    outcome
+}
+
+/// Collect where `actual` differs from `expected`, as JSON pointer paths
+fn diff_values(path: &str, expected: &Value, actual: &Value, differences: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, value) in expected {
+                let path = format!("{path}/{key}");
+                match actual.get(key) {
+                    Some(other) => diff_values(&path, value, other, differences),
+                    None => differences.push(format!("{path}: missing, expected {value}")),
+                }
+            }
+            for (key, value) in actual.iter().filter(|(key, _)| !expected.contains_key(*key)) {
+                differences.push(format!("{path}/{key}: unexpected {value}"));
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) if expected.len() == actual.len() => {
+            for (n, (value, other)) in expected.iter().zip(actual).enumerate() {
+                diff_values(&format!("{path}/{n}"), value, other, differences);
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            let path = if path.is_empty() { "/" } else { path };
+            differences.push(format!("{path}: {} items, expected {}", actual.len(), expected.len()));
+        }
+        _ if expected != actual => {
+            let path = if path.is_empty() { "/" } else { path };
+            differences.push(format!("{path}: {actual}, expected {expected}"));
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(direction: Direction, ms: u64, msg: Value) -> String {
+        entry_line(direction, &Message::from(msg), Some(1), Duration::from_millis(ms))
+    }
+
+    #[test]
+    fn recorded_sessions_split_into_script_answers_and_expectations() {
+        let recording = [
+            line(Direction::FromClient, 0, json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}})),
+            line(Direction::ToRoslyn, 1, json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}})),
+            line(Direction::ToClient, 40, json!({"jsonrpc": "2.0", "id": 1, "result": {"capabilities": {}}})),
+            line(Direction::ToClient, 50, json!({"jsonrpc": "2.0", "id": "roslyn-wrapper/1", "method": "workspace/configuration", "params": {}})),
+            line(Direction::FromClient, 55, json!({"jsonrpc": "2.0", "id": "roslyn-wrapper/1", "result": [true]})),
+            line(Direction::FromClient, 60, json!({"jsonrpc": "2.0", "method": "initialized", "params": {}})),
+        ]
+        .concat();
+
+        let session = Session::parse(&recording).unwrap();
+        let script: Vec<_> = session.script.iter().map(|(ms, after, _)| (*ms, *after)).collect();
+        assert_eq!(script, vec![(0, 0), (60, 1)]);
+        assert_eq!(session.answers["workspace/configuration"][0]["result"], json!([true]));
+        assert_eq!(session.expected.len(), 1);
+        assert_eq!(session.expected[0].0, json!(1));
+        assert_eq!(session.expected[0].1, "initialize");
+    }
+
+    #[test]
+    fn differences_are_reported_by_path() {
+        let expected = json!({"result": {"items": [{"label": "Foo"}, {"label": "Bar"}], "isIncomplete": false}});
+        let actual = json!({"result": {"items": [{"label": "Foo"}, {"label": "Baz"}], "extra": 1}});
+        let mut differences = Vec::new();
+        diff_values("", &expected, &actual, &mut differences);
+        assert_eq!(
+            differences,
+            vec![
+                "/result/isIncomplete: missing, expected false",
+                "/result/items/1/label: \"Baz\", expected \"Bar\"",
+                "/result/extra: unexpected 1",
+            ]
+        );
+    }
+}
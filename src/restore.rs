@@ -0,0 +1,89 @@
+// `dotnet restore` for projects Roslyn reports through `workspace/_roslyn_projectNeedsRestore`.
+// Editors other than VS Code don't know that request, so the wrapper answers it by
+// restoring the projects itself and streaming the output back to the router.
+use std::process::Stdio;
+
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+
+/// Progress of a running restore
+#[derive(Debug, Clone, PartialEq)]
+pub enum Update {
+    /// A line of `dotnet restore` output
+    Output(String),
+    /// Every project was attempted; `failed` lists those that did not restore
+    Finished { failed: Vec<String> },
+}
+
+/// Project files from the request's `projectFilePaths`
+pub fn requested_projects(params: Option<&Value>) -> Vec<String> {
+    params
+        .and_then(|p| p.get("projectFilePaths"))
+        .and_then(Value::as_array)
+        .map(|paths| paths.iter().filter_map(Value::as_str).map(String::from).collect())
+        .unwrap_or_default()
+}
+
+/// Restore `projects` one after another, reporting through `events` wrapped by `event`
+pub async fn run<E>(projects: Vec<String>, events: mpsc::Sender<E>, event: fn(Update) -> E) {
+    let mut failed = Vec::new();
+    for project in projects {
+        let send = |update| events.send(event(update));
+        let _ = send(Update::Output(format!("Restoring {project}"))).await;
+        if let Err(e) = restore_project(&project, &events, event).await {
+            let _ = send(Update::Output(format!("dotnet restore {project} failed: {e}"))).await;
+            failed.push(project);
+        }
+    }
+    let _ = events.send(event(Update::Finished { failed })).await;
+}
+
+async fn restore_project<E>(project: &str, events: &mpsc::Sender<E>, event: fn(Update) -> E) -> Result<(), String> {
+    let mut child = Command::new("dotnet")
+        .args(["restore", project])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("cannot start dotnet: {e}"))?;
+
+    tokio::join!(
+        forward_lines(child.stdout.take(), events, event),
+        forward_lines(child.stderr.take(), events, event),
+    );
+
+    let status = child.wait().await.map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(status.to_string());
+    }
+    Ok(())
+}
+
+async fn forward_lines<R: AsyncRead + Unpin, E>(output: Option<R>, events: &mpsc::Sender<E>, event: fn(Update) -> E) {
+    let Some(output) = output else {
+        return;
+    };
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim();
+        if !line.is_empty() && events.send(event(Update::Output(line.to_string()))).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn projects_come_from_project_file_paths() {
+        let params = json!({ "projectFilePaths": ["/work/App/App.csproj", 3, "/work/Lib/Lib.csproj"] });
+        assert_eq!(requested_projects(Some(&params)), ["/work/App/App.csproj", "/work/Lib/Lib.csproj"]);
+        assert!(requested_projects(None).is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+// Per-method timeouts for client requests forwarded to Roslyn. Roslyn can leave a
+// request unanswered (e.g. while a project is loading); once its timeout passes the
+// wrapper cancels it on both sides instead of letting the editor wait forever.
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::logger;
+
+// Interactive requests the editor blocks typing or hovering on
+const INTERACTIVE_TIMEOUT: Duration = Duration::from_secs(15);
+const INTERACTIVE_METHODS: &[&str] = &[
+    "textDocument/completion",
+    "textDocument/hover",
+    "textDocument/signatureHelp",
+    "textDocument/documentHighlight",
+];
+
+// Never timed out: `initialize` has to finish before anything else works, and
+// `workspace/diagnostic` is a long-poll that Roslyn answers only when results change
+const UNLIMITED_METHODS: &[&str] = &["initialize", "workspace/diagnostic"];
+
+/// Timeouts by method, configured through `initializationOptions.requestTimeouts`:
+/// `{ "default": 60, "textDocument/codeAction": 30 }`, in seconds; `0` disables the
+/// timeout for that method. Methods without a timeout of their own wait indefinitely
+/// unless `default` is set.
+#[derive(Debug, Clone)]
+pub struct RequestTimeouts {
+    default: Option<Duration>,
+    per_method: HashMap<String, Option<Duration>>,
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        let interactive = INTERACTIVE_METHODS
+            .iter()
+            .map(|method| (method.to_string(), Some(INTERACTIVE_TIMEOUT)));
+        let unlimited = UNLIMITED_METHODS.iter().map(|method| (method.to_string(), None));
+        Self {
+            default: None,
+            per_method: interactive.chain(unlimited).collect(),
+        }
+    }
+}
+
+impl RequestTimeouts {
+    /// How long a request may stay unanswered, or `None` to wait indefinitely
+    pub fn for_method(&self, method: &str) -> Option<Duration> {
+        match self.per_method.get(method) {
+            Some(timeout) => *timeout,
+            None => self.default,
+        }
+    }
+
+    /// Apply overrides from `initializationOptions.requestTimeouts`
+    pub fn configure(&mut self, options: &Value) {
+        let Some(options) = options.as_object() else {
+            logger::warn("[roslyn_wrapper] Ignoring requestTimeouts: expected an object");
+            return;
+        };
+        for (method, seconds) in options {
+            let Some(seconds) = seconds.as_f64().filter(|s| s.is_finite() && *s >= 0.0) else {
+                logger::warn(format!(
+                    "[roslyn_wrapper] Ignoring request timeout for {method}: expected seconds"
+                ));
+                continue;
+            };
+            let timeout = match Duration::try_from_secs_f64(seconds) {
+                Ok(timeout) => (seconds > 0.0).then_some(timeout),
+                Err(_) => {
+                    logger::warn(format!(
+                        "[roslyn_wrapper] Request timeout for {method} is out of range; not timing it out"
+                    ));
+                    None
+                }
+            };
+            if method == "default" {
+                self.default = timeout;
+            } else {
+                self.per_method.insert(method.clone(), timeout);
+            }
+        }
+        logger::info("[roslyn_wrapper] Applied request timeouts from initializationOptions");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn overrides_replace_builtin_timeouts() {
+        let mut timeouts = RequestTimeouts::default();
+        assert_eq!(timeouts.for_method("textDocument/hover"), Some(INTERACTIVE_TIMEOUT));
+        assert_eq!(timeouts.for_method("workspace/diagnostic"), None);
+        assert_eq!(timeouts.for_method("textDocument/references"), None);
+
+        timeouts.configure(&json!({ "default": 60 }));
+        assert_eq!(timeouts.for_method("textDocument/references"), Some(Duration::from_secs(60)));
+
+        timeouts.configure(&json!({
+            "default": 0,
+            "textDocument/hover": 2.5,
+            "workspace/diagnostic": 120,
+            "textDocument/codeAction": "soon"
+        }));
+        assert_eq!(timeouts.for_method("textDocument/hover"), Some(Duration::from_millis(2500)));
+        assert_eq!(timeouts.for_method("workspace/diagnostic"), Some(Duration::from_secs(120)));
+        assert_eq!(timeouts.for_method("textDocument/references"), None);
+        assert_eq!(timeouts.for_method("textDocument/codeAction"), None);
+        assert_eq!(timeouts.for_method("textDocument/completion"), Some(INTERACTIVE_TIMEOUT));
+    }
+
+    #[test]
+    fn out_of_range_timeouts_mean_no_timeout() {
+        let mut timeouts = RequestTimeouts::default();
+        timeouts.configure(&json!({ "textDocument/hover": 1e300, "default": 1e20 }));
+        assert_eq!(timeouts.for_method("textDocument/hover"), None);
+        assert_eq!(timeouts.for_method("textDocument/references"), None);
+    }
+}
@@ -0,0 +1,267 @@
+use std::io;
+use std::path::PathBuf;
+
+use serde_json::Value;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Boxed reader/writer halves of an LSP connection, so the forwarding tasks
+/// work the same over stdio, sockets and named pipes.
+pub type LspReader = Box<dyn AsyncBufRead + Send + Unpin>;
+pub type LspWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// How the wrapper talks to the Roslyn process
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RoslynTransport {
+    /// LSP over Roslyn's stdin/stdout (`--stdio`)
+    Stdio,
+    /// LSP over a named pipe / Unix domain socket announced on stdout (`--pipe`)
+    Pipe,
+}
+
+impl RoslynTransport {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "stdio" => Some(RoslynTransport::Stdio),
+            "pipe" => Some(RoslynTransport::Pipe),
+            _ => None,
+        }
+    }
+
+    /// Name accepted by [`RoslynTransport::parse`] (`--roslyn-transport`)
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RoslynTransport::Stdio => "stdio",
+            RoslynTransport::Pipe => "pipe",
+        }
+    }
+
+    /// Roslyn command-line flag selecting this transport
+    pub fn roslyn_arg(self) -> &'static str {
+        match self {
+            RoslynTransport::Stdio => "--stdio",
+            RoslynTransport::Pipe => "--pipe",
+        }
+    }
+}
+
+/// Read Roslyn's stdout until it announces the pipe to connect to.
+///
+/// In `--pipe` mode Roslyn prints a single JSON object such as
+/// `{"pipeName":"/tmp/abc.sock"}`. Anything printed before it is logged and skipped.
+pub async fn read_pipe_name<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Roslyn exited before announcing its pipe name",
+            ));
+        }
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        if let Ok(value) = serde_json::from_str::<Value>(trimmed) {
+            if let Some(name) = value.get("pipeName").and_then(|v| v.as_str()) {
+                return Ok(name.to_string());
+            }
+        }
+        crate::logger::debug(format!("[roslyn][stdout] {trimmed}"));
+    }
+}
+
+/// Connect to the pipe announced by Roslyn and split it into reader/writer halves
+#[cfg(unix)]
+pub async fn connect_pipe(name: &str) -> io::Result<(LspReader, LspWriter)> {
+    let stream = tokio::net::UnixStream::connect(name).await?;
+    let (read, write) = stream.into_split();
+    Ok((Box::new(BufReader::new(read)), Box::new(write)))
+}
+
+/// Connect to the pipe announced by Roslyn and split it into reader/writer halves
+#[cfg(windows)]
+pub async fn connect_pipe(name: &str) -> io::Result<(LspReader, LspWriter)> {
+    // Roslyn may report the bare pipe name on Windows
+    let path = if name.starts_with(r"\\.\pipe\") {
+        name.to_string()
+    } else {
+        format!(r"\\.\pipe\{name}")
+    };
+    let pipe = tokio::net::windows::named_pipe::ClientOptions::new().open(path)?;
+    let (read, write) = tokio::io::split(pipe);
+    Ok((Box::new(BufReader::new(read)), Box::new(write)))
+}
+
+/// Address the wrapper serves editor connections on (`--listen`)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ListenAddr {
+    /// `tcp:PORT` (loopback) or `tcp:HOST:PORT`
+    Tcp(String),
+    /// `unix:PATH`
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    pub fn parse(s: &str) -> Option<Self> {
+        if let Some(rest) = s.strip_prefix("tcp:") {
+            if rest.parse::<u16>().is_ok() {
+                return Some(ListenAddr::Tcp(format!("127.0.0.1:{rest}")));
+            }
+            let (host, port) = rest.rsplit_once(':')?;
+            if host.is_empty() || port.parse::<u16>().is_err() {
+                return None;
+            }
+            return Some(ListenAddr::Tcp(rest.to_string()));
+        }
+        if let Some(rest) = s.strip_prefix("unix:") {
+            if !rest.is_empty() {
+                return Some(ListenAddr::Unix(PathBuf::from(rest)));
+            }
+        }
+        None
+    }
+}
+
+impl std::fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "tcp:{addr}"),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Listening socket accepting editor connections
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+impl Listener {
+    pub async fn bind(addr: &ListenAddr) -> io::Result<Self> {
+        match addr {
+            ListenAddr::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                // A socket file left behind by a previous run would make bind fail, but
+                // one a live server still answers on is not ours to remove
+                if path.exists() {
+                    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            format!("{} is in use by a running server", path.display()),
+                        ));
+                    }
+                    std::fs::remove_file(path)?;
+                }
+                let listener = tokio::net::UnixListener::bind(path)?;
+                Ok(Listener::Unix(listener, path.clone()))
+            }
+            #[cfg(not(unix))]
+            ListenAddr::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix domain sockets are not supported on this platform",
+            )),
+        }
+    }
+
+    /// Wait for the next client and split the connection into reader/writer halves
+    pub async fn accept(&self) -> io::Result<(LspReader, LspWriter)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                crate::logger::info(format!("[roslyn_wrapper] Accepted client from {peer}"));
+                split_tcp(stream)
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                let (stream, _) = listener.accept().await?;
+                crate::logger::info("[roslyn_wrapper] Accepted client on Unix socket");
+                let (read, write) = stream.into_split();
+                Ok((Box::new(BufReader::new(read)), Box::new(write)))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn split_tcp(stream: TcpStream) -> io::Result<(LspReader, LspWriter)> {
+    stream.set_nodelay(true)?;
+    let (read, write) = stream.into_split();
+    Ok((Box::new(BufReader::new(read)), Box::new(write)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_test::block_on;
+
+    #[test]
+    fn transport_names_parse_back() {
+        for transport in [RoslynTransport::Stdio, RoslynTransport::Pipe] {
+            assert_eq!(RoslynTransport::parse(transport.as_str()), Some(transport));
+        }
+    }
+
+    #[test]
+    fn pipe_name_skips_stray_output() {
+        let stdout = b"Starting server...\n\n{\"pipeName\":\"/tmp/roslyn.sock\"}\n";
+        let mut reader = &stdout[..];
+        assert_eq!(
+            block_on(read_pipe_name(&mut reader)).unwrap(),
+            "/tmp/roslyn.sock"
+        );
+    }
+
+    #[test]
+    fn listen_addr_parsing() {
+        assert_eq!(
+            ListenAddr::parse("tcp:9000"),
+            Some(ListenAddr::Tcp("127.0.0.1:9000".to_string()))
+        );
+        assert_eq!(
+            ListenAddr::parse("tcp:0.0.0.0:9000"),
+            Some(ListenAddr::Tcp("0.0.0.0:9000".to_string()))
+        );
+        assert_eq!(
+            ListenAddr::parse("unix:/tmp/roslyn.sock"),
+            Some(ListenAddr::Unix(PathBuf::from("/tmp/roslyn.sock")))
+        );
+        assert_eq!(ListenAddr::parse("tcp:notaport"), None);
+        assert_eq!(ListenAddr::parse("9000"), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_bind_replaces_stale_sockets_but_not_live_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.sock");
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let addr = ListenAddr::Unix(path.clone());
+        let _live = Listener::bind(&addr).await.unwrap();
+        let err = Listener::bind(&addr).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(tokio::net::UnixStream::connect(&path).await.is_ok());
+    }
+
+    #[test]
+    fn pipe_name_eof_is_an_error() {
+        let mut reader = &b"no pipe here\n"[..];
+        assert!(block_on(read_pipe_name(&mut reader)).is_err());
+    }
+}